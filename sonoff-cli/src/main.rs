@@ -1,14 +1,31 @@
-use anyhow::{Result, Context};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::{Duration, SystemTime};
+
+use anyhow::{bail, Result, Context};
 use clap::{Parser, Subcommand};
+use futures::StreamExt;
 
+use sonoff_lib::client::SonoffClient;
 use sonoff_lib::device::SonoffDevice;
+use sonoff_lib::discovery::discover;
+use sonoff_lib::ota::OtaProgress;
+use sonoff_lib::watch::{watch_with_keys, StateChange};
 use sonoff_lib::bulb::SonoffBulb;
-use sonoff_lib::bulb::DevReqBulbColorType;
+use sonoff_lib::bulb::{DevReqBulbColorType, Scene, TransitionMode};
+use sonoff_lib::color::{self, BulbModel, Hsv};
 use sonoff_lib::switch::SonoffSwitch;
-use sonoff_lib::dimmer::SonoffDimmer;
+use sonoff_lib::dimmer::{DimmerMode, SonoffDimmer};
 
-use sonoff_lib::switchable::SonoffSwitchable;
+use sonoff_lib::switchable::{PulseState, SonoffSwitchable, StartupState, SwitchState};
+use sonoff_lib::mini_r3::SonoffMiniR3;
+use sonoff_lib::multi_switchable::SonoffMultiSwitchable;
+use sonoff_lib::power_meter::{SonoffPowerMeter, PowerReading, OverloadConfig, SPM_OUTLETS};
+use sonoff_lib::energy::{self, HourlyEnergy, LoggedReading};
 use sonoff_lib::dimmable::SonoffDimmable;
+use sonoff_lib::fade::FadeCurve;
+use sonoff_lib::values::{Brightness, ColorTemp, PulseWidth, Rgb};
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -16,14 +33,31 @@ struct Cli {
     /// Debug mode
     #[arg(long, default_value_t = false)]
     debug: bool,
-    /// Address of device
-    address: String,
+    /// Request timeout in seconds
+    #[arg(long)]
+    timeout: Option<u64>,
+    /// Device ID to look up on the local network, instead of an address
+    #[arg(long)]
+    id: Option<String>,
+    /// Device key, for devices in eWeLink LAN (encrypted) mode
+    #[arg(long, requires = "id")]
+    device_key: Option<String>,
+    /// Address of device (e.g. http://192.168.1.2:8081)
+    address: Option<String>,
     #[command(subcommand)]
     command: Option<Command>,
 }
 
 #[derive(Subcommand)]
 enum Command {
+    /// List devices on the local network
+    Discover {
+        /// Seconds to wait for announcements
+        #[arg(long, default_value_t = 3)]
+        wait: u64,
+    },
+    /// Print state changes announced by devices on the local network
+    Watch,
     /// Get information about the device
     Info,
     /// Set device Wi-Fi network
@@ -31,6 +65,16 @@ enum Command {
         ssid: String,
         password: String,
     },
+    /// Get or set the network indicator LED
+    Led {
+        #[command(subcommand)]
+        led_cmd: Option<LedCommand>,
+    },
+    /// Flash firmware over the air
+    Ota {
+        #[command(subcommand)]
+        ota_cmd: Option<OtaCommand>,
+    },
     /// Get or set switch state on switchable devices
     Switch {
         #[command(subcommand)]
@@ -38,6 +82,9 @@ enum Command {
     },
     /// Get or set settings on bulb devices
     Bulb {
+        /// Bulb model, for the Kelvin calibration
+        #[arg(long, value_enum, default_value_t = BulbModel::B05Bl)]
+        model: BulbModel,
         #[command(subcommand)]
         bulb_cmd: Option<BulbCommand>,
     },
@@ -45,6 +92,158 @@ enum Command {
         #[command(subcommand)]
         dimmer_cmd: Option<DimmerCommand>,
     },
+    /// Get or set outlets of MINIR3 devices
+    Minir3 {
+        #[command(subcommand)]
+        minir3_cmd: Option<MultiSwitchCommand>,
+    },
+    /// Manage, read and configure SPM-MAIN sub-devices
+    Spm {
+        #[command(subcommand)]
+        spm_cmd: Option<SpmCommand>,
+    },
+}
+
+#[derive(Subcommand)]
+enum SpmCommand {
+    /// List paired sub-devices
+    List,
+    /// Pair a sub-device by the ID on its label (experimental)
+    Add {
+        sub_dev_id: String,
+    },
+    /// Unpair a sub-device (experimental)
+    Remove {
+        sub_dev_id: String,
+    },
+    /// Blink the LED of a sub-device (experimental)
+    Identify {
+        sub_dev_id: String,
+        #[arg(long, value_parser = parse_duration, default_value = "10s")]
+        duration: Duration,
+    },
+    /// Get or set outlets of a sub-device
+    Outlets {
+        sub_dev_id: String,
+        #[command(subcommand)]
+        outlets_cmd: Option<MultiSwitchCommand>,
+    },
+    /// Live power readings of a sub-device
+    Power {
+        sub_dev_id: String,
+        /// Outlet (0-3), all of them if missing
+        outlet: Option<u8>,
+    },
+    /// Hourly energy history of an outlet (experimental)
+    Energy {
+        sub_dev_id: String,
+        /// Outlet (0-3)
+        outlet: u8,
+        /// Days back from now (max 30)
+        #[arg(long, default_value_t = 7)]
+        days: u64,
+        #[arg(long, value_enum, default_value_t = ExportFormat::Text)]
+        format: ExportFormat,
+    },
+    /// Readings of an outlet logged by the device (experimental)
+    Log {
+        sub_dev_id: String,
+        /// Outlet (0-3)
+        outlet: u8,
+        /// Hours back from now (max 720)
+        #[arg(long, default_value_t = 24)]
+        hours: u64,
+        #[arg(long, value_enum, default_value_t = ExportFormat::Text)]
+        format: ExportFormat,
+    },
+    /// Print real-time readings until interrupted (experimental)
+    Monitor {
+        sub_dev_id: String,
+        /// Outlet (0-3), all of them if missing
+        #[arg(value_parser = clap::value_parser!(u8).range(..i64::from(SPM_OUTLETS)))]
+        outlet: Option<u8>,
+    },
+    /// Print active faults. Exits with status 2 if there is any, or 1 if a
+    /// sub-device could not be read.
+    Faults {
+        /// Sub-device, all of them if missing
+        sub_dev_id: Option<String>,
+    },
+    /// Get or set overload protection of an outlet
+    Overload {
+        #[command(subcommand)]
+        overload_cmd: OverloadCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum OverloadCommand {
+    /// Show the limits of an outlet
+    Get {
+        sub_dev_id: String,
+        /// Outlet (0-3)
+        outlet: u8,
+    },
+    /// Change some limits, others keep their current value (experimental)
+    Set {
+        sub_dev_id: String,
+        /// Outlet (0-3)
+        outlet: u8,
+        /// Minimum active power in watts
+        #[arg(long)]
+        min_power: Option<f64>,
+        /// Maximum active power in watts
+        #[arg(long)]
+        max_power: Option<f64>,
+        /// Minimum voltage in volts
+        #[arg(long)]
+        min_voltage: Option<f64>,
+        /// Maximum voltage in volts
+        #[arg(long)]
+        max_voltage: Option<f64>,
+        /// Maximum current in amperes
+        #[arg(long)]
+        max_current: Option<f64>,
+        /// Seconds a limit must be exceeded before turning the outlet off
+        #[arg(long)]
+        delay: Option<u32>,
+        /// Limits to disable
+        #[arg(long, value_enum, value_delimiter = ',')]
+        disable: Vec<OverloadLimit>,
+    },
+}
+
+#[derive(Clone, Copy, PartialEq, clap::ValueEnum)]
+enum ExportFormat {
+    Text,
+    Csv,
+    Json,
+}
+
+#[derive(Clone, Copy, PartialEq, clap::ValueEnum)]
+enum OverloadLimit {
+    MinPower,
+    MaxPower,
+    MinVoltage,
+    MaxVoltage,
+    MaxCurrent,
+}
+
+#[derive(Subcommand)]
+enum LedCommand {
+    On,
+    Off,
+    Get,
+}
+
+#[derive(Subcommand)]
+enum OtaCommand {
+    /// Allow flashing firmware (the device needs Internet access)
+    Unlock,
+    /// Flash a firmware image, served from this machine
+    Flash {
+        path: PathBuf,
+    },
 }
 
 #[derive(Subcommand)]
@@ -53,12 +252,14 @@ enum SwitchCommand {
     Off,
     Toggle,
     Get,
+    /// Set pulse width (multiple of 500, or 0 to deactivate pulse)
     Pulse {
         milliseconds: u32,
     },
-    /// Set startup state ("on", "off", or "stay")
+    /// Set startup state
     Startup {
-        startup: String,
+        #[arg(value_enum)]
+        startup: StartupState,
     }
 }
 
@@ -79,6 +280,39 @@ enum BulbCommand {
         brightness: u8,
         temperature: u8,
     },
+    /// Set the color from hex, HSV, a CSS color name, or Kelvin
+    #[command(group(clap::ArgGroup::new("color").required(true)))]
+    Color {
+        /// Color as #rrggbb
+        #[arg(long, group = "color")]
+        hex: Option<String>,
+        /// Color as h,s,v (hue in degrees, saturation and value in percent)
+        #[arg(long, group = "color")]
+        hsv: Option<Hsv>,
+        /// CSS color name (e.g. "coral")
+        #[arg(long, group = "color")]
+        name: Option<String>,
+        /// White color temperature (e.g. 2700)
+        #[arg(long, group = "color", requires = "brightness")]
+        kelvin: Option<u16>,
+        /// Brightness (1-100), instead of the one of the color
+        #[arg(long)]
+        brightness: Option<u8>,
+    },
+    /// Switch to a built-in scene
+    Scene {
+        #[arg(value_enum)]
+        scene: Scene,
+        /// Brightness instead of the scene default
+        #[arg(long)]
+        brightness: Option<u8>,
+        /// Transition mode of dynamic scenes
+        #[arg(long, value_enum, requires = "speed")]
+        transition: Option<TransitionMode>,
+        /// Transition speed of dynamic scenes (1-100)
+        #[arg(long, requires = "transition")]
+        speed: Option<u8>,
+    },
 }
 
 #[derive(Subcommand)]
@@ -90,10 +324,176 @@ enum DimmerCommand {
     Dim {
         brightness: u8
     },
-    /// Set startup state ("on", "off", or "stay")
+    /// Set startup state
+    Startup {
+        #[arg(value_enum)]
+        startup: StartupState,
+    },
+    /// Fade to a brightness
+    Fade {
+        brightness: u8,
+        /// Duration (e.g. "30s", "500ms", "2m")
+        #[arg(long, value_parser = parse_duration, default_value = "1s")]
+        over: Duration,
+        #[arg(long, value_enum, default_value_t = FadeCurve::Linear)]
+        curve: FadeCurve,
+    },
+    /// Set the dimming mode
+    Mode {
+        #[arg(value_enum)]
+        mode: DimmerMode,
+    },
+    /// Set the output levels (0-100) for the lowest and highest brightness
+    #[command(group(clap::ArgGroup::new("levels").required(true).multiple(true)))]
+    Calibrate {
+        #[arg(long, group = "levels")]
+        min: Option<u8>,
+        #[arg(long, group = "levels")]
+        max: Option<u8>,
+    },
+}
+
+/// Parses a duration such as "30s", "500ms" or "2m". Plain numbers are
+/// seconds.
+fn parse_duration(s: &str) -> Result<Duration> {
+    let (number, unit) = s.find(|c: char| !c.is_ascii_digit() && c != '.')
+        .map_or((s, "s"), |i| s.split_at(i));
+    let number: f64 = number.parse().context("Invalid duration")?;
+    let seconds = match unit {
+        "ms" => number / 1000.0,
+        "s" => number,
+        "m" => number * 60.0,
+        _ => bail!("Invalid duration unit {unit:?}"),
+    };
+    Ok(Duration::try_from_secs_f64(seconds)?)
+}
+
+#[derive(Subcommand)]
+enum MultiSwitchCommand {
+    On {
+        outlet: u8,
+    },
+    Off {
+        outlet: u8,
+    },
+    Toggle {
+        outlet: u8,
+    },
+    Get,
+    /// Set pulse in milliseconds, 0 to deactivate it
+    Pulse {
+        outlet: u8,
+        milliseconds: u32,
+    },
+    /// Set startup state
     Startup {
-        startup: String,
+        outlet: u8,
+        #[arg(value_enum)]
+        startup: StartupState,
+    },
+}
+
+async fn multi_switch(relay: &dyn SonoffMultiSwitchable, cmd: MultiSwitchCommand) -> Result<()> {
+    match cmd {
+        MultiSwitchCommand::On { outlet } => { relay.outlet_on(outlet).await?; },
+        MultiSwitchCommand::Off { outlet } => { relay.outlet_off(outlet).await?; },
+        MultiSwitchCommand::Toggle { outlet } => { relay.outlet_toggle(outlet).await?; },
+        MultiSwitchCommand::Get => {
+            for (outlet, on) in relay.get_outlets().await?.into_iter().enumerate() {
+                println!("outlet{}={}", outlet, SwitchState::from(on));
+            }
+        },
+        MultiSwitchCommand::Pulse { outlet, milliseconds } => {
+            let width = match milliseconds {
+                0 => None,
+                ms => Some(PulseWidth::try_from(ms)?),
+            };
+            relay.set_outlet_pulse(outlet, width).await?;
+        },
+        MultiSwitchCommand::Startup { outlet, startup } => {
+            relay.set_outlet_startup(outlet, startup).await?;
+        },
     }
+    Ok(())
+}
+
+fn print_reading(reading: &PowerReading) {
+    let power_factor = reading.power_factor.map_or("-".to_owned(), |pf| format!("{pf:.2}"));
+    println!(
+        "outlet{} current={:.2}A voltage={:.2}V power={:.2}W reactive={:.2}var apparent={:.2}VA pf={}",
+        reading.outlet, reading.current, reading.voltage, reading.active_power,
+        reading.reactive_power, reading.apparent_power, power_factor,
+    );
+}
+
+/// Formats Unix time as "YYYY-MM-DDTHH:MM:SSZ"
+fn format_utc(timestamp: u64) -> String {
+    let (days, secs) = (timestamp / 86400, timestamp % 86400);
+    // Civil date from days since 1970-01-01 (proleptic Gregorian calendar)
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z", secs / 3600, secs % 3600 / 60, secs % 60)
+}
+
+fn print_energy(hours: &[HourlyEnergy], format: ExportFormat) -> Result<()> {
+    match format {
+        ExportFormat::Text => {
+            for hour in hours {
+                println!("{} {:.2}kWh", format_utc(hour.timestamp), hour.kwh);
+            }
+            println!("total={:.2}kWh", energy::total_kwh(hours));
+        },
+        ExportFormat::Csv => {
+            println!("time,timestamp,kwh");
+            for hour in hours {
+                println!("{},{},{:.2}", format_utc(hour.timestamp), hour.timestamp, hour.kwh);
+            }
+        },
+        ExportFormat::Json => println!("{}", serde_json::to_string_pretty(hours)?),
+    }
+    Ok(())
+}
+
+fn print_log(log: &[LoggedReading], format: ExportFormat) -> Result<()> {
+    match format {
+        ExportFormat::Text => {
+            for entry in log {
+                print!("{} ", format_utc(entry.timestamp));
+                print_reading(&entry.reading);
+            }
+        },
+        ExportFormat::Csv => {
+            println!("time,timestamp,current,voltage,active_power,reactive_power,apparent_power,power_factor");
+            for entry in log {
+                let r = &entry.reading;
+                let power_factor = r.power_factor.map_or(String::new(), |pf| format!("{pf:.2}"));
+                println!(
+                    "{},{},{:.2},{:.2},{:.2},{:.2},{:.2},{}",
+                    format_utc(entry.timestamp), entry.timestamp, r.current, r.voltage,
+                    r.active_power, r.reactive_power, r.apparent_power, power_factor,
+                );
+            }
+        },
+        ExportFormat::Json => println!("{}", serde_json::to_string_pretty(log)?),
+    }
+    Ok(())
+}
+
+fn print_overload(config: &OverloadConfig) {
+    let limit = |value: Option<f64>, unit: &str| value.map_or("off".to_owned(), |v| format!("{v:.2}{unit}"));
+    println!("min_power={}", limit(config.min_power, "W"));
+    println!("max_power={}", limit(config.max_power, "W"));
+    println!("min_voltage={}", limit(config.min_voltage, "V"));
+    println!("max_voltage={}", limit(config.max_voltage, "V"));
+    println!("max_current={}", limit(config.max_current, "A"));
+    println!("delay={}s", config.delay);
 }
 
 async fn get_info(dev: &SonoffDevice) -> Result<()> {
@@ -104,18 +504,99 @@ async fn get_info(dev: &SonoffDevice) -> Result<()> {
     println!("signal_strength={}", dev_info.signal_strength.unwrap_or_default());
     println!("fw_version={}", dev_info.fw_version.unwrap_or_default());
     println!("ota_unlock={}", dev_info.ota_unlock.unwrap_or_default());
+    if let Some(sled_online) = dev_info.sled_online {
+        println!("led={sled_online}");
+    }
     Ok(())
 }
 
-async fn cli() -> Result<()> {
+async fn cli() -> Result<ExitCode> {
     let args = Cli::parse();
-    let dev = SonoffDevice::new(&args.address);
+    let mut client = SonoffClient::builder();
+    if let Some(timeout) = args.timeout {
+        client = client.timeout(Duration::from_secs(timeout));
+    }
+    let client = client.build()?;
     let cmd = args.command.context("No command")?;
+    if let Command::Discover { wait } = cmd {
+        for found in discover(Duration::from_secs(wait)).await? {
+            println!("{} {} {}", found.id, found.device_type, found.address().unwrap_or_default());
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+    if let Command::Watch = cmd {
+        let keys = match (args.id, args.device_key) {
+            (Some(id), Some(key)) => HashMap::from([(id, key)]),
+            _ => HashMap::new(),
+        };
+        let events = watch_with_keys(keys)?;
+        futures::pin_mut!(events);
+        while let Some(event) = events.next().await {
+            let seq = event.seq.map(|seq| seq.to_string()).unwrap_or_default();
+            match event.change {
+                StateChange::Online(found) => println!("{} {} online {}", event.id, seq, found.address().unwrap_or_default()),
+                StateChange::Offline => println!("{} {} offline", event.id, seq),
+                StateChange::Switch(on) => println!("{} {} switch={}", event.id, seq, on),
+                StateChange::Outlet { outlet, on } => println!("{} {} outlet{}={}", event.id, seq, outlet, on),
+                StateChange::Brightness(br) => println!("{} {} brightness={}", event.id, seq, br),
+                StateChange::Other { key, value } => println!("{} {} {}={}", event.id, seq, key, value),
+                StateChange::Removed { key } => println!("{} {} {} removed", event.id, seq, key),
+            }
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+    let dev = match (args.address, args.id) {
+        (Some(address), id) => {
+            let mut dev = client.device(address);
+            dev.id = id.unwrap_or_default();
+            dev
+        },
+        (None, Some(id)) => {
+            let found = discover(Duration::from_secs(3)).await?;
+            let found = found.iter().find(|found| found.id == id)
+                .context("Device not found")?;
+            found.device(&client).context("Device has no address")?
+        },
+        (None, None) => anyhow::bail!("No address or device ID"),
+    };
+    let dev = match args.device_key {
+        Some(device_key) => dev.with_device_key(device_key),
+        None => dev,
+    };
     match cmd {
+        Command::Discover { .. } | Command::Watch => unreachable!(),
         Command::Info => get_info(&dev).await?,
         Command::Wifi { ssid, password } => {
             dev.set_wifi(ssid, password).await?;
         },
+        Command::Ota { ota_cmd } => {
+            match ota_cmd.context("Invalid OTA command")? {
+                OtaCommand::Unlock => { dev.unlock_ota().await?; },
+                OtaCommand::Flash { path } => {
+                    let mut last_percent = None;
+                    dev.flash_ota_with_progress(path, |progress| match progress {
+                        OtaProgress::Downloading { sent, total } => {
+                            let percent = sent * 100 / total.max(1);
+                            if last_percent != Some(percent) {
+                                println!("downloading {percent}%");
+                                last_percent = Some(percent);
+                            }
+                        },
+                        OtaProgress::Rebooting => println!("rebooting"),
+                        OtaProgress::Done => println!("done"),
+                    }).await?;
+                },
+            }
+        },
+        Command::Led { led_cmd } => {
+            match led_cmd.context("Invalid LED command")? {
+                LedCommand::On => { dev.set_led(true).await?; },
+                LedCommand::Off => { dev.set_led(false).await?; },
+                LedCommand::Get => {
+                    println!("{}", SwitchState::from(dev.get_led().await?));
+                },
+            }
+        },
         Command::Switch { switch_cmd } => {
             let switch = SonoffSwitch::from(&dev);
             match switch_cmd.context("Invalid switch command")? {
@@ -125,16 +606,19 @@ async fn cli() -> Result<()> {
                 SwitchCommand::Get => {
                     println!("{}", switch.get_switch().await?);
                 },
+                SwitchCommand::Pulse { milliseconds: 0 } => {
+                    switch.pulse_off().await?;
+                },
                 SwitchCommand::Pulse { milliseconds } => {
-                    switch.pulse(milliseconds).await?;
-                }
+                    switch.pulse(PulseWidth::try_from(milliseconds)?).await?;
+                },
                 SwitchCommand::Startup { startup } => {
                     switch.set_startup(startup).await?;
                 },
             }
         },
-        Command::Bulb { bulb_cmd } => {
-            let bulb = SonoffBulb::from(&dev);
+        Command::Bulb { model, bulb_cmd } => {
+            let bulb = SonoffBulb::from(&dev).with_model(model);
             match bulb_cmd.context("Invalid bulb command")? {
                 BulbCommand::On => { bulb.on().await?; },
                 BulbCommand::Off => { bulb.off().await?; },
@@ -148,18 +632,57 @@ async fn cli() -> Result<()> {
                             println!("red={}", rgb_bulb_info.r);
                             println!("green={}", rgb_bulb_info.g);
                             println!("blue={}", rgb_bulb_info.b);
+                            println!("hex={}", color::to_hex((rgb_bulb_info.r, rgb_bulb_info.g, rgb_bulb_info.b)));
                         },
                         DevReqBulbColorType::White(white_bulb_info) => {
                             println!("brightness={}", white_bulb_info.br);
                             println!("temperature={}", white_bulb_info.ct);
+                            println!("kelvin={}", bulb.calibration().from_device(&white_bulb_info));
+                        },
+                        color_type => if let Some((scene, scene_info)) = color_type.scene() {
+                            println!("scene={}", scene);
+                            println!("brightness={}", scene_info.br);
+                            if let (Some(tf), Some(sp)) = (scene_info.tf, scene_info.sp) {
+                                println!("transition={}", tf);
+                                println!("speed={}", sp);
+                            }
                         },
                     }
                 },
                 BulbCommand::Rgb { brightness, red, green, blue } => {
-                    bulb.color(brightness, red, green, blue).await?;
+                    let rgb = Rgb::new(red, green, blue)?;
+                    bulb.color(Brightness::try_from(brightness)?, rgb).await?;
                 },
                 BulbCommand::White { brightness, temperature } => {
-                    bulb.white(brightness, temperature).await?;
+                    bulb.white(Brightness::try_from(brightness)?, ColorTemp::try_from(temperature)?).await?;
+                },
+                BulbCommand::Color { hex, hsv, name, kelvin, brightness } => {
+                    // clap requires --brightness along with --kelvin
+                    if let (Some(kelvin), Some(brightness)) = (kelvin, brightness) {
+                        bulb.kelvin(Brightness::try_from(brightness)?, kelvin).await?;
+                    } else {
+                        let hsv = match (hex, hsv, name) {
+                            (Some(hex), _, _) => Hsv::from_rgb(color::parse_hex(&hex)?),
+                            (_, Some(hsv), _) => hsv,
+                            (_, _, Some(name)) => {
+                                let rgb = color::named(&name).context(format!("Unknown color {name:?}"))?;
+                                Hsv::from_rgb(rgb)
+                            },
+                            (None, None, None) => bail!("One of --hex, --hsv, --name, or --kelvin is required"),
+                        };
+                        let hsv = match brightness {
+                            Some(br) => Hsv { v: Brightness::try_from(br)?.get() as f32 / 100.0, ..hsv },
+                            None => hsv,
+                        };
+                        bulb.hsv(hsv).await?;
+                    }
+                },
+                BulbCommand::Scene { scene, brightness, transition, speed } => {
+                    let br = match brightness {
+                        Some(br) => Brightness::try_from(br)?,
+                        None => Brightness::try_from(scene.defaults().br)?,
+                    };
+                    bulb.scene_with(scene, br, transition.zip(speed)).await?;
                 },
             }
         },
@@ -172,22 +695,183 @@ async fn cli() -> Result<()> {
                 DimmerCommand::Get => {
                     let dimmer_info = dimmer.get_info().await?;
                     println!("switch={}", dimmer_info.switch);
+                    println!("startup={}", dimmer_info.startup);
                     println!("brightness={}", dimmer_info.brightness);
+                    match DimmerMode::try_from(dimmer_info.mode) {
+                        Ok(mode) => println!("mode={mode}"),
+                        Err(_) => println!("mode={}", dimmer_info.mode),
+                    }
+                    println!("brightmin={}", dimmer_info.brightmin);
+                    println!("brightmax={}", dimmer_info.brightmax);
                 },
                 DimmerCommand::Dim { brightness } => {
-                    dimmer.dim(brightness).await?;
+                    dimmer.dim(Brightness::try_from(brightness)?).await?;
                 },
                 DimmerCommand::Startup { startup } => {
                     dimmer.set_startup(startup).await?;
+                },
+                DimmerCommand::Fade { brightness, over, curve } => {
+                    dimmer.fade_to(Brightness::try_from(brightness)?, over, curve).await?;
+                },
+                DimmerCommand::Mode { mode } => {
+                    dimmer.set_mode(mode).await?;
+                },
+                DimmerCommand::Calibrate { min, max } => {
+                    dimmer.configure(None, min, max).await?;
+                },
+            }
+        },
+        Command::Minir3 { minir3_cmd } => {
+            let mini_r3 = SonoffMiniR3::from(&dev);
+            let minir3_cmd = minir3_cmd.context("Invalid MINIR3 command")?;
+            if let MultiSwitchCommand::Get = minir3_cmd {
+                let info = mini_r3.get_info().await?;
+                for switch in info.switches {
+                    println!("outlet{}={}", switch.outlet, switch.switch);
+                }
+                for startup in info.configure {
+                    println!("startup{}={}", startup.outlet, startup.startup);
                 }
+                for pulse in info.pulses {
+                    println!("pulse{}={}", pulse.outlet, pulse.pulse);
+                    if pulse.pulse == PulseState::On {
+                        println!("pulse_width{}={}", pulse.outlet, pulse.width);
+                    }
+                }
+            } else {
+                multi_switch(&mini_r3, minir3_cmd).await?;
+            }
+        },
+        Command::Spm { spm_cmd } => {
+            let spm = SonoffPowerMeter::from(&dev);
+            match spm_cmd.context("Invalid SPM command")? {
+                SpmCommand::List => {
+                    for subdev in spm.get_subdevs().await?.sub_dev_list {
+                        println!("{} type={}", subdev.sub_dev_id, subdev.kind);
+                    }
+                },
+                SpmCommand::Add { sub_dev_id } => { spm.add_subdev(sub_dev_id).await?; },
+                SpmCommand::Remove { sub_dev_id } => { spm.remove_subdev(sub_dev_id).await?; },
+                SpmCommand::Identify { sub_dev_id, duration } => {
+                    spm.identify_subdev(sub_dev_id, duration).await?;
+                },
+                SpmCommand::Outlets { sub_dev_id, outlets_cmd: None | Some(MultiSwitchCommand::Get) } => {
+                    let status = spm.subdev_status(sub_dev_id).await?;
+                    for switch in status.switches {
+                        println!("outlet{}={}", switch.outlet, switch.switch);
+                    }
+                    for startup in status.configure {
+                        println!("startup{}={}", startup.outlet, startup.startup);
+                    }
+                    for pulse in status.pulses {
+                        println!("pulse{}={}", pulse.outlet, pulse.pulse);
+                        if pulse.pulse == PulseState::On {
+                            println!("pulse_width{}={}", pulse.outlet, pulse.width);
+                        }
+                    }
+                },
+                SpmCommand::Outlets { sub_dev_id, outlets_cmd: Some(outlets_cmd) } => {
+                    multi_switch(&spm.sub_device(sub_dev_id), outlets_cmd).await?;
+                },
+                SpmCommand::Power { sub_dev_id, outlet: Some(outlet) } => {
+                    print_reading(&spm.read_power(sub_dev_id, outlet).await?);
+                },
+                SpmCommand::Power { sub_dev_id, outlet: None } => {
+                    for reading in spm.read_power_all(sub_dev_id).await? {
+                        print_reading(&reading);
+                    }
+                },
+                SpmCommand::Energy { sub_dev_id, outlet, days, format } => {
+                    let end = SystemTime::now();
+                    let start = end.checked_sub(Duration::from_secs(days.saturating_mul(24 * 3600)))
+                        .context("Invalid number of days")?;
+                    print_energy(&spm.energy_history(sub_dev_id, outlet, start, end).await?, format)?;
+                },
+                SpmCommand::Log { sub_dev_id, outlet, hours, format } => {
+                    let end = SystemTime::now();
+                    let start = end.checked_sub(Duration::from_secs(hours.saturating_mul(3600)))
+                        .context("Invalid number of hours")?;
+                    print_log(&spm.data_log(sub_dev_id, outlet, start, end).await?, format)?;
+                },
+                SpmCommand::Monitor { sub_dev_id, outlet } => {
+                    let readings = spm.monitor(sub_dev_id).await?;
+                    futures::pin_mut!(readings);
+                    while let Some(reading) = readings.next().await {
+                        let reading = reading?;
+                        if matches!(outlet, Some(outlet) if outlet != reading.outlet) {
+                            continue;
+                        }
+                        print_reading(&reading);
+                    }
+                },
+                SpmCommand::Faults { sub_dev_id } => {
+                    let sub_dev_ids = match sub_dev_id {
+                        Some(sub_dev_id) => vec![sub_dev_id],
+                        None => spm.get_subdevs().await?.sub_dev_list.into_iter().map(|s| s.sub_dev_id).collect(),
+                    };
+                    let (mut failed, mut faulty) = (false, false);
+                    for sub_dev_id in sub_dev_ids {
+                        match spm.faults(sub_dev_id.to_owned()).await {
+                            Ok(report) => {
+                                println!("{report}");
+                                faulty |= !report.is_ok();
+                            },
+                            Err(err) => {
+                                eprintln!("{sub_dev_id}: {err}");
+                                failed = true;
+                            },
+                        }
+                    }
+                    if failed {
+                        return Ok(ExitCode::FAILURE);
+                    }
+                    if faulty {
+                        return Ok(ExitCode::from(2));
+                    }
+                },
+                SpmCommand::Overload { overload_cmd: OverloadCommand::Get { sub_dev_id, outlet } } => {
+                    print_overload(&spm.get_overload(sub_dev_id, outlet).await?);
+                },
+                SpmCommand::Overload { overload_cmd: OverloadCommand::Set {
+                    sub_dev_id, outlet, min_power, max_power, min_voltage, max_voltage, max_current, delay, disable,
+                } } => {
+                    let limits = [
+                        (OverloadLimit::MinPower, min_power),
+                        (OverloadLimit::MaxPower, max_power),
+                        (OverloadLimit::MinVoltage, min_voltage),
+                        (OverloadLimit::MaxVoltage, max_voltage),
+                        (OverloadLimit::MaxCurrent, max_current),
+                    ];
+                    if limits.iter().any(|(limit, value)| value.is_some() && disable.contains(limit)) {
+                        bail!("Cannot both set and disable a limit");
+                    }
+                    let config = spm.update_overload(sub_dev_id, outlet, |config| {
+                        for (limit, value) in limits {
+                            let target = match limit {
+                                OverloadLimit::MinPower => &mut config.min_power,
+                                OverloadLimit::MaxPower => &mut config.max_power,
+                                OverloadLimit::MinVoltage => &mut config.min_voltage,
+                                OverloadLimit::MaxVoltage => &mut config.max_voltage,
+                                OverloadLimit::MaxCurrent => &mut config.max_current,
+                            };
+                            if disable.contains(&limit) {
+                                *target = None;
+                            } else if value.is_some() {
+                                *target = value;
+                            }
+                        }
+                        config.delay = delay.unwrap_or(config.delay);
+                    }).await?;
+                    print_overload(&config);
+                },
             }
         },
     }
 
-    Ok(())
+    Ok(ExitCode::SUCCESS)
 }
 
 #[tokio::main(flavor = "current_thread")]
-async fn main() -> Result<()> {
+async fn main() -> Result<ExitCode> {
     cli().await
 }
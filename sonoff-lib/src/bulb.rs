@@ -1,17 +1,23 @@
-use anyhow::Result;
 use async_trait::async_trait;
 use serde::{Serialize, Deserialize};
 
 use crate::device::{SonoffDevice, DevRes};
-use crate::switchable::SonoffSwitchable;
+use crate::error::{Result, SonoffError};
+use crate::switchable::{SonoffSwitchable, SwitchState};
 use crate::dimmable::SonoffDimmable;
+use crate::colorable::{SonoffColor, SonoffColorTemp};
+use crate::values::{check_range, Brightness, ColorTemp, Rgb};
+use crate::color::{BulbModel, Hsv, KelvinCalibration};
+
+/// Lowest brightness accepted by bulbs, which are off at 0
+pub const BRIGHTNESS_MIN: u8 = 1;
 
 // JSON models
 // ===================================================================
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct DevInfoDataBulb {
-    pub switch: String,
+    pub switch: SwitchState,
     pub ltype: String,
     #[serde(flatten)]
     pub color_type: DevReqBulbColorType,
@@ -19,17 +25,25 @@ pub struct DevInfoDataBulb {
 
 #[derive(Debug, Serialize, Deserialize)]
 struct DevReqBulb {
-    /// Lamp type ("color" or "white")
+    /// Lamp type ("color", "white", or the name of a scene)
     pub ltype: String,
     #[serde(flatten)]
     pub color_type: DevReqBulbColorType,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
-#[serde(rename_all = "lowercase")]
+#[serde(rename_all = "camelCase")]
 pub enum DevReqBulbColorType {
     Color(DevReqBulbColorTypeRGB),
     White(DevReqBulbColorTypeCW),
+    Bright(DevReqBulbScene),
+    GoodNight(DevReqBulbScene),
+    Read(DevReqBulbScene),
+    NightLight(DevReqBulbScene),
+    Party(DevReqBulbScene),
+    Leisure(DevReqBulbScene),
+    Soft(DevReqBulbScene),
+    Colorful(DevReqBulbScene),
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -52,16 +66,209 @@ pub struct DevReqBulbColorTypeCW {
     pub ct: u8,
 }
 
+/// Settings of a scene. Unlike in `DevReqBulbColorTypeRGB`, channels may be
+/// 0, as in the settings the eWeLink app uses (see `Scene::defaults`).
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DevReqBulbScene {
+    /// Brightness (min=1, max=100)
+    pub br: u8,
+    /// Red (min=0, max=255)
+    pub r: u8,
+    /// Green (min=0, max=255)
+    pub g: u8,
+    /// Blue (min=0, max=255)
+    pub b: u8,
+    /// Transition mode of dynamic scenes (see `TransitionMode`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tf: Option<u8>,
+    /// Transition speed of dynamic scenes (min=1, max=100)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sp: Option<u8>,
+}
+
+/// Built-in scenes of color bulbs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+#[serde(rename_all = "camelCase")]
+pub enum Scene {
+    Bright,
+    GoodNight,
+    Read,
+    NightLight,
+    /// Dynamic
+    Party,
+    /// Dynamic
+    Leisure,
+    /// Dynamic
+    Soft,
+    /// Dynamic
+    Colorful,
+}
+
+/// How dynamic scenes move from one color to the next
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+pub enum TransitionMode {
+    /// Jump to the next color
+    Jump,
+    /// Fade into the next color
+    Gradient,
+}
+
 // Implementation
 // ===================================================================
 
+impl DevReqBulbColorType {
+    /// Value of `ltype` for this color type
+    pub fn ltype(&self) -> &'static str {
+        match self {
+            DevReqBulbColorType::Color(_) => "color",
+            DevReqBulbColorType::White(_) => "white",
+            DevReqBulbColorType::Bright(_) => "bright",
+            DevReqBulbColorType::GoodNight(_) => "goodNight",
+            DevReqBulbColorType::Read(_) => "read",
+            DevReqBulbColorType::NightLight(_) => "nightLight",
+            DevReqBulbColorType::Party(_) => "party",
+            DevReqBulbColorType::Leisure(_) => "leisure",
+            DevReqBulbColorType::Soft(_) => "soft",
+            DevReqBulbColorType::Colorful(_) => "colorful",
+        }
+    }
+
+    /// Brightness, whatever the color type. Scenes without one are at full
+    /// brightness.
+    pub fn br(&self) -> u8 {
+        match self {
+            DevReqBulbColorType::Color(rgb) => rgb.br,
+            DevReqBulbColorType::White(cw) => cw.br,
+            _ => self.scene().map_or(Brightness::MAX, |(_, scene)| scene.br),
+        }
+    }
+
+    /// Mutable brightness, whatever the color type
+    pub fn br_mut(&mut self) -> &mut u8 {
+        match self {
+            DevReqBulbColorType::Color(rgb) => &mut rgb.br,
+            DevReqBulbColorType::White(cw) => &mut cw.br,
+            DevReqBulbColorType::Bright(scene)
+                | DevReqBulbColorType::GoodNight(scene)
+                | DevReqBulbColorType::Read(scene)
+                | DevReqBulbColorType::NightLight(scene)
+                | DevReqBulbColorType::Party(scene)
+                | DevReqBulbColorType::Leisure(scene)
+                | DevReqBulbColorType::Soft(scene)
+                | DevReqBulbColorType::Colorful(scene) => &mut scene.br,
+        }
+    }
+
+    /// Scene the bulb is in, if any
+    pub fn scene(&self) -> Option<(Scene, &DevReqBulbScene)> {
+        match self {
+            DevReqBulbColorType::Color(_) | DevReqBulbColorType::White(_) => None,
+            DevReqBulbColorType::Bright(s) => Some((Scene::Bright, s)),
+            DevReqBulbColorType::GoodNight(s) => Some((Scene::GoodNight, s)),
+            DevReqBulbColorType::Read(s) => Some((Scene::Read, s)),
+            DevReqBulbColorType::NightLight(s) => Some((Scene::NightLight, s)),
+            DevReqBulbColorType::Party(s) => Some((Scene::Party, s)),
+            DevReqBulbColorType::Leisure(s) => Some((Scene::Leisure, s)),
+            DevReqBulbColorType::Soft(s) => Some((Scene::Soft, s)),
+            DevReqBulbColorType::Colorful(s) => Some((Scene::Colorful, s)),
+        }
+    }
+}
+
+impl Scene {
+    /// Whether the scene cycles through colors
+    pub fn is_dynamic(self) -> bool {
+        matches!(self, Scene::Party | Scene::Leisure | Scene::Soft | Scene::Colorful)
+    }
+
+    /// Default settings of the scene, as set by the eWeLink app
+    pub fn defaults(self) -> DevReqBulbScene {
+        let (br, r, g, b) = match self {
+            Scene::Bright => (100, 255, 255, 255),
+            Scene::GoodNight => (25, 254, 254, 126),
+            Scene::Read => (60, 255, 255, 255),
+            Scene::NightLight => (5, 255, 242, 226),
+            Scene::Party => (45, 254, 132, 0),
+            Scene::Leisure => (55, 0, 40, 254),
+            Scene::Soft => (20, 38, 254, 0),
+            Scene::Colorful => (100, 255, 0, 0),
+        };
+        let (tf, sp) = if self.is_dynamic() {
+            (Some(TransitionMode::Gradient.into()), Some(1))
+        } else {
+            (None, None)
+        };
+        DevReqBulbScene { br, r, g, b, tf, sp }
+    }
+
+    /// Request for this scene with `settings`
+    pub fn color_type(self, settings: DevReqBulbScene) -> DevReqBulbColorType {
+        match self {
+            Scene::Bright => DevReqBulbColorType::Bright(settings),
+            Scene::GoodNight => DevReqBulbColorType::GoodNight(settings),
+            Scene::Read => DevReqBulbColorType::Read(settings),
+            Scene::NightLight => DevReqBulbColorType::NightLight(settings),
+            Scene::Party => DevReqBulbColorType::Party(settings),
+            Scene::Leisure => DevReqBulbColorType::Leisure(settings),
+            Scene::Soft => DevReqBulbColorType::Soft(settings),
+            Scene::Colorful => DevReqBulbColorType::Colorful(settings),
+        }
+    }
+}
+
+/// Kebab-case, like the command line (e.g. "good-night")
+impl std::fmt::Display for Scene {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Scene::Bright => "bright",
+            Scene::GoodNight => "good-night",
+            Scene::Read => "read",
+            Scene::NightLight => "night-light",
+            Scene::Party => "party",
+            Scene::Leisure => "leisure",
+            Scene::Soft => "soft",
+            Scene::Colorful => "colorful",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl From<TransitionMode> for u8 {
+    fn from(value: TransitionMode) -> Self {
+        match value {
+            TransitionMode::Jump => 0,
+            TransitionMode::Gradient => 1,
+        }
+    }
+}
+
+impl TryFrom<u8> for TransitionMode {
+    type Error = SonoffError;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(TransitionMode::Jump),
+            1 => Ok(TransitionMode::Gradient),
+            _ => Err(SonoffError::InvalidValue(format!("transition mode must be 0 or 1, got {value}"))),
+        }
+    }
+}
+
+pub(crate) fn check_brightness(br: u8) -> Result<u8> {
+    check_range("brightness", br, BRIGHTNESS_MIN, Brightness::MAX)
+}
+
 pub struct SonoffBulb {
-    dev: SonoffDevice
+    dev: SonoffDevice,
+    calibration: KelvinCalibration,
 }
 
+/// Assumes a B05-BL (see `with_model`)
 impl From<&SonoffDevice> for SonoffBulb {
     fn from(value: &SonoffDevice) -> Self {
-        SonoffBulb { dev: value.to_owned() }
+        SonoffBulb { dev: value.to_owned(), calibration: KelvinCalibration::B05_BL }
     }
 }
 
@@ -71,21 +278,86 @@ impl SonoffSwitchable for SonoffBulb {
 }
 
 impl SonoffBulb {
+    /// Uses the Kelvin calibration of `model`
+    pub fn with_model(mut self, model: BulbModel) -> SonoffBulb {
+        self.calibration = model.calibration();
+        self
+    }
+
+    pub fn calibration(&self) -> KelvinCalibration { self.calibration }
+
+    /// Values are checked against the ranges accepted by the device before
+    /// sending anything.
     pub async fn set_bulb(&self, color_type: DevReqBulbColorType) -> Result<DevRes> {
-        let ltype = match color_type {
-            DevReqBulbColorType::Color(_) => "color",
-            DevReqBulbColorType::White(_) => "white",
-        }.to_owned();
+        // Waits for read-modify-write sequences (e.g. dim), which would
+        // otherwise overwrite this color with the one they read
+        let _guard = self.dev.state_lock.lock().await;
+        self.send_bulb(color_type).await
+    }
+
+    /// `set_bulb` for callers already holding `state_lock`
+    async fn send_bulb(&self, color_type: DevReqBulbColorType) -> Result<DevRes> {
+        match &color_type {
+            DevReqBulbColorType::Color(rgb) => {
+                check_brightness(rgb.br)?;
+                Rgb::new(rgb.r, rgb.g, rgb.b)?;
+            },
+            DevReqBulbColorType::White(cw) => {
+                check_brightness(cw.br)?;
+                ColorTemp::try_from(cw.ct)?;
+            },
+            _ => if let Some((_, scene)) = color_type.scene() {
+                check_brightness(scene.br)?;
+                if let Some(tf) = scene.tf {
+                    TransitionMode::try_from(tf)?;
+                }
+                if let Some(sp) = scene.sp {
+                    check_range("transition speed", sp, 1, 100)?;
+                }
+            },
+        }
+        let ltype = color_type.ltype().to_owned();
         let req_obj = DevReqBulb { ltype, color_type };
         self.dev.__request("/dimmable".to_owned(), req_obj).await
     }
 
-    pub async fn color(&self, br: u8, r: u8, g: u8, b: u8) -> Result<DevRes> {
-        self.set_bulb(DevReqBulbColorType::Color(DevReqBulbColorTypeRGB { br, r, g, b, })).await
+    pub async fn color(&self, br: Brightness, rgb: Rgb) -> Result<DevRes> {
+        let (r, g, b) = rgb.into();
+        self.set_bulb(DevReqBulbColorType::Color(DevReqBulbColorTypeRGB { br: br.get(), r, g, b, })).await
+    }
+
+    pub async fn white(&self, br: Brightness, ct: ColorTemp) -> Result<DevRes> {
+        self.set_bulb(DevReqBulbColorType::White(DevReqBulbColorTypeCW { br: br.get(), ct: ct.get() })).await
+    }
+
+    /// Sets the color from HSV. The value becomes the brightness.
+    pub async fn hsv(&self, hsv: Hsv) -> Result<DevRes> {
+        self.set_bulb(DevReqBulbColorType::Color(hsv.to_device()?)).await
+    }
+
+    /// Sets a white color temperature in Kelvin (see `calibration`)
+    pub async fn kelvin(&self, br: Brightness, kelvin: u16) -> Result<DevRes> {
+        self.set_bulb(DevReqBulbColorType::White(self.calibration.to_device(br, kelvin)?)).await
+    }
+
+    /// Switches to `scene` with its default settings
+    pub async fn scene(&self, scene: Scene) -> Result<DevRes> {
+        self.set_bulb(scene.color_type(scene.defaults())).await
     }
 
-    pub async fn white(&self, br: u8, ct: u8) -> Result<DevRes> {
-        self.set_bulb(DevReqBulbColorType::White(DevReqBulbColorTypeCW { br, ct })).await
+    /// Switches to `scene` with brightness `br`. The transition mode and
+    /// speed (min=1, max=100) only apply to dynamic scenes.
+    pub async fn scene_with(&self, scene: Scene, br: Brightness, transition: Option<(TransitionMode, u8)>) -> Result<DevRes> {
+        let mut settings = scene.defaults();
+        settings.br = br.get();
+        if let Some((mode, speed)) = transition {
+            if !scene.is_dynamic() {
+                return Err(SonoffError::InvalidValue(format!("scene {scene} has no transitions")));
+            }
+            settings.tf = Some(mode.into());
+            settings.sp = Some(speed);
+        }
+        self.set_bulb(scene.color_type(settings)).await
     }
 
     pub async fn get_info(&self) -> Result<DevInfoDataBulb> {
@@ -96,20 +368,193 @@ impl SonoffBulb {
 
 #[async_trait]
 impl SonoffDimmable for SonoffBulb {
-    async fn dim(&self, br: u8) -> Result<DevRes> {
-        // TODO: dimming must not change color
-        self.white(br, 100).await
+    /// Changes only the brightness, keeping the current color, color
+    /// temperature, or scene.
+    async fn dim(&self, br: Brightness) -> Result<DevRes> {
+        // Another dim() between reading and writing would be overwritten
+        // with a stale color
+        let _guard = self.dev.state_lock.lock().await;
+        let mut color_type = self.get_info().await?.color_type;
+        *color_type.br_mut() = br.get();
+        self.send_bulb(color_type).await
+    }
+
+    async fn get_brightness(&self) -> Result<Brightness> {
+        Ok(Brightness::saturating(self.get_info().await?.color_type.br()))
+    }
+}
+
+#[async_trait]
+impl SonoffColor for SonoffBulb {
+    async fn set_color(&self, rgb: Rgb) -> Result<DevRes> {
+        let _guard = self.dev.state_lock.lock().await;
+        let br = self.get_info().await?.color_type.br();
+        let (r, g, b) = rgb.into();
+        self.send_bulb(DevReqBulbColorType::Color(DevReqBulbColorTypeRGB { br, r, g, b })).await
+    }
+
+    async fn get_color(&self) -> Result<Option<Rgb>> {
+        match self.get_info().await?.color_type {
+            DevReqBulbColorType::Color(rgb) => Ok(Some(Rgb::saturating(rgb.r, rgb.g, rgb.b))),
+            _ => Ok(None),
+        }
+    }
+}
+
+#[async_trait]
+impl SonoffColorTemp for SonoffBulb {
+    async fn set_color_temp(&self, ct: ColorTemp) -> Result<DevRes> {
+        let _guard = self.dev.state_lock.lock().await;
+        let br = self.get_info().await?.color_type.br();
+        self.send_bulb(DevReqBulbColorType::White(DevReqBulbColorTypeCW { br, ct: ct.get() })).await
+    }
+
+    async fn get_color_temp(&self) -> Result<Option<ColorTemp>> {
+        match self.get_info().await?.color_type {
+            DevReqBulbColorType::White(cw) => Ok(Some(ColorTemp::saturating(cw.ct))),
+            _ => Ok(None),
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::sync::Arc;
+    use serde_json::json;
     use super::*;
+    use crate::transport::MockTransport;
 
     #[tokio::test]
     async fn test_bulb_white() {
-        let dev = SonoffDevice::new("http://127.0.0.1:8081");
+        let mock = Arc::new(MockTransport::with_data(json!({})));
+        let dev = SonoffDevice::with_transport(mock.clone());
         let bulb = SonoffBulb::from(&dev);
         bulb.on().await.unwrap();
+        bulb.white(80.try_into().unwrap(), 50.try_into().unwrap()).await.unwrap();
+        assert_eq!(mock.requests()[1].1["data"], json!({
+            "ltype": "white",
+            "white": { "br": 80, "ct": 50 },
+        }));
+    }
+
+    #[tokio::test]
+    async fn test_bulb_kelvin() {
+        let mock = Arc::new(MockTransport::with_data(json!({})));
+        let dev = SonoffDevice::with_transport(mock.clone());
+        let bulb = SonoffBulb::from(&dev).with_model(BulbModel::B02Bl);
+        bulb.kelvin(80.try_into().unwrap(), 4600).await.unwrap();
+        assert_eq!(mock.requests()[0].1["data"], json!({
+            "ltype": "white",
+            "white": { "br": 80, "ct": 50 },
+        }));
+    }
+
+    #[tokio::test]
+    async fn test_bulb_invalid() {
+        let mock = Arc::new(MockTransport::with_data(json!({})));
+        let dev = SonoffDevice::with_transport(mock.clone());
+        let bulb = SonoffBulb::from(&dev);
+        let rgb = DevReqBulbColorTypeRGB { br: 0, r: 255, g: 1, b: 1 };
+        let err = bulb.set_bulb(DevReqBulbColorType::Color(rgb)).await.unwrap_err();
+        assert!(matches!(err, SonoffError::InvalidValue(_)));
+        let rgb = DevReqBulbColorTypeRGB { br: 40, r: 255, g: 0, b: 0 };
+        let err = bulb.set_bulb(DevReqBulbColorType::Color(rgb)).await.unwrap_err();
+        assert!(matches!(err, SonoffError::InvalidValue(_)));
+        assert!(mock.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_bulb_dim() {
+        let mock = Arc::new(MockTransport::new(|url_path, _| match url_path {
+            "/info" => json!({ "seq": 1, "error": 0, "data": {
+                "deviceid": "1000abcdef",
+                "switch": "on",
+                "ltype": "color",
+                "color": { "br": 100, "r": 255, "g": 1, "b": 1 },
+            }}),
+            _ => json!({ "seq": 2, "error": 0 }),
+        }));
+        let dev = SonoffDevice::with_transport(mock.clone());
+        let bulb = SonoffBulb::from(&dev);
+        bulb.dim(30.try_into().unwrap()).await.unwrap();
+        assert_eq!(mock.requests()[1].1["data"], json!({
+            "ltype": "color",
+            "color": { "br": 30, "r": 255, "g": 1, "b": 1 },
+        }));
+    }
+
+    #[tokio::test]
+    async fn test_bulb_color_traits() {
+        let mock = Arc::new(MockTransport::new(|url_path, _| match url_path {
+            "/info" => json!({ "seq": 1, "error": 0, "data": {
+                "deviceid": "1000abcdef",
+                "switch": "on",
+                "ltype": "white",
+                "white": { "br": 40, "ct": 20 },
+            }}),
+            _ => json!({ "seq": 2, "error": 0 }),
+        }));
+        let dev = SonoffDevice::with_transport(mock.clone());
+        let bulb = SonoffBulb::from(&dev);
+        assert_eq!(bulb.get_brightness().await.unwrap().get(), 40);
+        assert_eq!(bulb.get_color_temp().await.unwrap(), Some(ColorTemp::try_from(20).unwrap()));
+        assert_eq!(bulb.get_color().await.unwrap(), None);
+        bulb.set_color(Rgb::new(1, 1, 255).unwrap()).await.unwrap();
+        assert_eq!(mock.requests().last().unwrap().1["data"], json!({
+            "ltype": "color",
+            "color": { "br": 40, "r": 1, "g": 1, "b": 255 },
+        }));
+    }
+
+    #[tokio::test]
+    async fn test_bulb_reported_state() {
+        // What the device reports is not validated like user input
+        let mock = Arc::new(MockTransport::with_data(json!({
+            "deviceid": "1000abcdef",
+            "switch": "on",
+            "ltype": "color",
+            "color": { "br": 0, "r": 255, "g": 0, "b": 0 },
+        })));
+        let bulb = SonoffBulb::from(&SonoffDevice::with_transport(mock));
+        assert_eq!(bulb.get_color().await.unwrap(), Some(Rgb::new(255, 1, 1).unwrap()));
+        assert_eq!(bulb.get_brightness().await.unwrap().get(), 0);
+        assert_eq!(bulb.get_color_temp().await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_bulb_scene() {
+        let mock = Arc::new(MockTransport::with_data(json!({})));
+        let dev = SonoffDevice::with_transport(mock.clone());
+        let bulb = SonoffBulb::from(&dev);
+        bulb.scene(Scene::NightLight).await.unwrap();
+        bulb.scene_with(Scene::Party, 80.try_into().unwrap(), Some((TransitionMode::Jump, 50))).await.unwrap();
+        assert_eq!(mock.requests()[0].1["data"], json!({
+            "ltype": "nightLight",
+            "nightLight": { "br": 5, "r": 255, "g": 242, "b": 226 },
+        }));
+        assert_eq!(mock.requests()[1].1["data"], json!({
+            "ltype": "party",
+            "party": { "br": 80, "r": 254, "g": 132, "b": 0, "tf": 0, "sp": 50 },
+        }));
+        let err = bulb.scene_with(Scene::Read, 80.try_into().unwrap(), Some((TransitionMode::Jump, 50))).await;
+        assert!(matches!(err, Err(SonoffError::InvalidValue(_))));
+        let err = bulb.scene_with(Scene::Soft, 80.try_into().unwrap(), Some((TransitionMode::Jump, 0))).await;
+        assert!(matches!(err, Err(SonoffError::InvalidValue(_))));
+
+        let info: DevInfoDataBulb = serde_json::from_value(json!({
+            "switch": "on",
+            "ltype": "goodNight",
+            "goodNight": { "br": 25, "r": 254, "g": 254, "b": 126 },
+        })).unwrap();
+        assert_eq!(info.color_type.scene().unwrap().0, Scene::GoodNight);
+    }
+
+    #[test]
+    fn test_scene_names() {
+        assert_eq!(Scene::GoodNight.to_string(), "good-night");
+        #[cfg(feature = "clap")]
+        for scene in <Scene as clap::ValueEnum>::value_variants() {
+            assert_eq!(<Scene as clap::ValueEnum>::from_str(&scene.to_string(), false), Ok(*scene));
+        }
     }
 }
@@ -0,0 +1,213 @@
+use std::net::IpAddr;
+use std::time::Duration;
+
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::RequestBuilder;
+
+use crate::device::SonoffDevice;
+use crate::error::Result;
+
+// Implementation
+// ===================================================================
+
+/// HTTP client shared by any number of `SonoffDevice` handles.
+///
+/// Cloning is cheap and clones share the same connection pool.
+#[derive(Clone, Debug)]
+pub struct SonoffClient {
+    client: reqwest::Client,
+}
+
+#[derive(Debug)]
+pub struct SonoffClientBuilder {
+    connect_timeout: Option<Duration>,
+    timeout: Option<Duration>,
+    local_address: Option<IpAddr>,
+    headers: HeaderMap,
+    title_case_headers: bool,
+}
+
+impl Default for SonoffClientBuilder {
+    fn default() -> Self {
+        SonoffClientBuilder {
+            connect_timeout: None,
+            timeout: None,
+            local_address: None,
+            headers: HeaderMap::new(),
+            // Some firmwares do not understand lowercase headers
+            title_case_headers: true,
+        }
+    }
+}
+
+impl SonoffClientBuilder {
+    /// Timeout for establishing the TCP connection
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Timeout for the whole request, from connecting until the response
+    /// body is read
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Local address to bind to (e.g. to pick the interface on the device
+    /// network)
+    pub fn local_address(mut self, addr: IpAddr) -> Self {
+        self.local_address = Some(addr);
+        self
+    }
+
+    /// Header sent with every request
+    pub fn header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.headers.insert(name, value);
+        self
+    }
+
+    /// Send headers in Title-Case (enabled by default)
+    pub fn title_case_headers(mut self, enabled: bool) -> Self {
+        self.title_case_headers = enabled;
+        self
+    }
+
+    pub fn build(self) -> Result<SonoffClient> {
+        let mut builder = reqwest::Client::builder()
+            .default_headers(self.headers)
+            .local_address(self.local_address);
+        if self.title_case_headers {
+            builder = builder.http1_title_case_headers();
+        }
+        if let Some(timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(timeout);
+        }
+        if let Some(timeout) = self.timeout {
+            builder = builder.timeout(timeout);
+        }
+        Ok(SonoffClient { client: builder.build()? })
+    }
+}
+
+impl Default for SonoffClient {
+    /// Client with default settings.
+    ///
+    /// # Panics
+    ///
+    /// Like `reqwest::Client::new`, panics if the TLS backend cannot be
+    /// initialized. Use `SonoffClient::new` to handle the error instead.
+    fn default() -> Self {
+        SonoffClient::new().expect("failed to build HTTP client")
+    }
+}
+
+impl SonoffClient {
+    pub fn new() -> Result<SonoffClient> {
+        SonoffClient::builder().build()
+    }
+
+    pub fn builder() -> SonoffClientBuilder {
+        SonoffClientBuilder::default()
+    }
+
+    /// Handle for the device at `address` (e.g. "http://192.168.1.2:8081")
+    pub fn device(&self, address: impl Into<String>) -> SonoffDevice {
+        SonoffDevice::with_client(self.clone(), address)
+    }
+
+    pub(crate) fn post(&self, url: impl AsRef<str>) -> RequestBuilder {
+        self.client.post(url.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use serde_json::json;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use super::*;
+
+    /// Keep-alive HTTP server answering every request with an empty reply.
+    /// Records the head of every request and counts connections.
+    async fn serve() -> (String, Arc<Mutex<Vec<String>>>, Arc<Mutex<usize>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+        let heads = Arc::new(Mutex::new(Vec::new()));
+        let connections = Arc::new(Mutex::new(0));
+        let (heads_srv, connections_srv) = (heads.clone(), connections.clone());
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                *connections_srv.lock().unwrap() += 1;
+                let heads = heads_srv.clone();
+                tokio::spawn(async move {
+                    let mut buf = Vec::new();
+                    let mut chunk = [0; 1024];
+                    loop {
+                        let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") else {
+                            match stream.read(&mut chunk).await {
+                                Ok(0) | Err(_) => return,
+                                Ok(n) => buf.extend_from_slice(&chunk[..n]),
+                            }
+                            continue;
+                        };
+                        let head = String::from_utf8_lossy(&buf[..end]).into_owned();
+                        let length: usize = head.lines()
+                            .find_map(|line| line.to_lowercase().strip_prefix("content-length:").map(|v| v.trim().parse().unwrap()))
+                            .unwrap_or(0);
+                        while buf.len() < end + 4 + length {
+                            match stream.read(&mut chunk).await {
+                                Ok(0) | Err(_) => return,
+                                Ok(n) => buf.extend_from_slice(&chunk[..n]),
+                            }
+                        }
+                        buf.drain(..end + 4 + length);
+                        heads.lock().unwrap().push(head);
+                        let body = json!({ "seq": 1, "error": 0, "data": {} }).to_string();
+                        let res = format!("HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}", body.len());
+                        if stream.write_all(res.as_bytes()).await.is_err() {
+                            return;
+                        }
+                    }
+                });
+            }
+        });
+        (address, heads, connections)
+    }
+
+    #[tokio::test]
+    async fn test_shared_client() {
+        let (address, heads, connections) = serve().await;
+        let client = SonoffClient::builder()
+            .connect_timeout(Duration::from_secs(2))
+            .timeout(Duration::from_secs(5))
+            .header(HeaderName::from_static("x-test"), HeaderValue::from_static("1"))
+            .build()
+            .unwrap();
+        let dev_a = client.device(address.clone());
+        let dev_b = client.device(address.clone());
+        assert_eq!(dev_a.address(), Some(address.as_str()));
+        dev_a.__request("/info", json!({})).await.unwrap();
+        dev_b.__request("/info", json!({})).await.unwrap();
+
+        // Both handles go through the same connection pool
+        assert_eq!(*connections.lock().unwrap(), 1);
+        let heads = heads.lock().unwrap();
+        assert_eq!(heads.len(), 2);
+        for head in heads.iter() {
+            assert!(head.starts_with("POST /zeroconf/info HTTP/1.1"), "{head}");
+            assert!(head.contains("\r\nX-Test: 1"), "{head}");
+            assert!(head.contains("\r\nContent-Length: "), "{head}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_lowercase_headers() {
+        let (address, heads, _) = serve().await;
+        let client = SonoffClient::builder().title_case_headers(false).build().unwrap();
+        client.device(address).__request("/info", json!({})).await.unwrap();
+        assert!(heads.lock().unwrap()[0].contains("\r\ncontent-length: "));
+    }
+}
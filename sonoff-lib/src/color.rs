@@ -0,0 +1,450 @@
+//! Conversions between common color models and the representation used by
+//! bulbs: a brightness (1-100) plus either r/g/b (1-255 each) or a color
+//! temperature (0-100).
+
+use std::str::FromStr;
+
+use crate::bulb::{check_brightness, DevReqBulbColorTypeCW, DevReqBulbColorTypeRGB};
+use crate::error::{Result, SonoffError};
+use crate::values::{check_range, Brightness, ColorTemp, Rgb};
+
+// Models
+// ===================================================================
+
+/// Hue (0-360 degrees), saturation (0-1) and value (0-1)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Hsv {
+    pub h: f32,
+    pub s: f32,
+    pub v: f32,
+}
+
+/// Hue (0-360 degrees), saturation (0-1) and lightness (0-1)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Hsl {
+    pub h: f32,
+    pub s: f32,
+    pub l: f32,
+}
+
+/// Bulb models with different white LEDs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+pub enum BulbModel {
+    B02Bl,
+    B05Bl,
+}
+
+/// Maps color temperatures of a bulb model to Kelvin, by linear
+/// interpolation between `points`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KelvinCalibration {
+    /// (ct, Kelvin) pairs, sorted by ct, from 0 (warm) to 100 (cold)
+    pub points: &'static [(u8, u16)],
+}
+
+// Implementation
+// ===================================================================
+
+impl Hsv {
+    pub fn new(h: f32, s: f32, v: f32) -> Result<Hsv> {
+        check_range("hue", h, 0.0, 360.0)?;
+        check_range("saturation", s, 0.0, 1.0)?;
+        check_range("value", v, 0.0, 1.0)?;
+        Ok(Hsv { h, s, v })
+    }
+
+    pub fn from_rgb((r, g, b): (u8, u8, u8)) -> Hsv {
+        let (r, g, b) = (r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0);
+        let max = r.max(g).max(b);
+        let delta = max - r.min(g).min(b);
+        let h = if delta == 0.0 {
+            0.0
+        } else if max == r {
+            60.0 * ((g - b) / delta).rem_euclid(6.0)
+        } else if max == g {
+            60.0 * ((b - r) / delta + 2.0)
+        } else {
+            60.0 * ((r - g) / delta + 4.0)
+        };
+        let s = if max == 0.0 { 0.0 } else { delta / max };
+        Hsv { h, s, v: max }
+    }
+
+    pub fn to_rgb(self) -> (u8, u8, u8) {
+        let c = self.v * self.s;
+        let h = (self.h % 360.0) / 60.0;
+        let x = c * (1.0 - (h % 2.0 - 1.0).abs());
+        let (r, g, b) = match h as u8 {
+            0 => (c, x, 0.0),
+            1 => (x, c, 0.0),
+            2 => (0.0, c, x),
+            3 => (0.0, x, c),
+            4 => (x, 0.0, c),
+            _ => (c, 0.0, x),
+        };
+        let m = self.v - c;
+        let channel = |value: f32| ((value + m) * 255.0).round() as u8;
+        (channel(r), channel(g), channel(b))
+    }
+
+    /// Device representation: the value becomes the brightness and the
+    /// color is sent at full intensity. Fails if the value is 0 (off).
+    pub fn to_device(self) -> Result<DevReqBulbColorTypeRGB> {
+        let br = check_brightness((self.v * 100.0).round() as u8)?;
+        let (r, g, b) = Hsv { v: 1.0, ..self }.to_rgb();
+        // Channels can't be 0
+        let rgb = Rgb::saturating(r, g, b);
+        Ok(DevReqBulbColorTypeRGB { br, r: rgb.r(), g: rgb.g(), b: rgb.b() })
+    }
+
+    pub fn from_device(rgb: &DevReqBulbColorTypeRGB) -> Hsv {
+        let hsv = Hsv::from_rgb((rgb.r, rgb.g, rgb.b));
+        Hsv { v: rgb.br as f32 / 100.0, ..hsv }
+    }
+}
+
+/// Parses "h,s,v", with the hue in degrees and the saturation and value in
+/// percent (e.g. "120,100,50")
+impl FromStr for Hsv {
+    type Err = SonoffError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || SonoffError::InvalidValue(format!("expected h,s,v, got {s:?}"));
+        let parts: Vec<f32> = s.split(',')
+            .map(|part| part.trim().parse().map_err(|_| invalid()))
+            .collect::<Result<_>>()?;
+        let [h, s, v] = parts[..] else {
+            return Err(invalid());
+        };
+        Hsv::new(h, s / 100.0, v / 100.0)
+    }
+}
+
+impl From<Hsl> for Hsv {
+    fn from(hsl: Hsl) -> Self {
+        let v = hsl.l + hsl.s * hsl.l.min(1.0 - hsl.l);
+        let s = if v == 0.0 { 0.0 } else { 2.0 * (1.0 - hsl.l / v) };
+        Hsv { h: hsl.h, s, v }
+    }
+}
+
+impl Hsl {
+    pub fn new(h: f32, s: f32, l: f32) -> Result<Hsl> {
+        check_range("hue", h, 0.0, 360.0)?;
+        check_range("saturation", s, 0.0, 1.0)?;
+        check_range("lightness", l, 0.0, 1.0)?;
+        Ok(Hsl { h, s, l })
+    }
+
+    pub fn from_rgb(rgb: (u8, u8, u8)) -> Hsl {
+        Hsv::from_rgb(rgb).into()
+    }
+
+    pub fn to_rgb(self) -> (u8, u8, u8) {
+        Hsv::from(self).to_rgb()
+    }
+}
+
+impl From<Hsv> for Hsl {
+    fn from(hsv: Hsv) -> Self {
+        let l = hsv.v * (1.0 - hsv.s / 2.0);
+        let s = if l == 0.0 || l == 1.0 { 0.0 } else { (hsv.v - l) / l.min(1.0 - l) };
+        Hsl { h: hsv.h, s, l }
+    }
+}
+
+/// Device representation of `rgb` (see `Hsv::to_device`)
+pub fn rgb_to_device(rgb: (u8, u8, u8)) -> Result<DevReqBulbColorTypeRGB> {
+    Hsv::from_rgb(rgb).to_device()
+}
+
+/// Color shown by the device, with the brightness applied
+pub fn rgb_from_device(rgb: &DevReqBulbColorTypeRGB) -> (u8, u8, u8) {
+    Hsv::from_device(rgb).to_rgb()
+}
+
+/// Parses "#rrggbb" (the "#" is optional)
+pub fn parse_hex(hex: &str) -> Result<(u8, u8, u8)> {
+    let digits = hex.strip_prefix('#').unwrap_or(hex);
+    let invalid = || SonoffError::InvalidValue(format!("expected #rrggbb, got {hex:?}"));
+    if digits.len() != 6 || !digits.is_ascii() {
+        return Err(invalid());
+    }
+    let channel = |i: usize| u8::from_str_radix(&digits[i..i + 2], 16).map_err(|_| invalid());
+    Ok((channel(0)?, channel(2)?, channel(4)?))
+}
+
+pub fn to_hex((r, g, b): (u8, u8, u8)) -> String {
+    format!("#{r:02x}{g:02x}{b:02x}")
+}
+
+/// Looks up a CSS named color (e.g. "coral"), ignoring case
+pub fn named(name: &str) -> Option<(u8, u8, u8)> {
+    CSS_COLORS.iter()
+        .find(|(css_name, _)| css_name.eq_ignore_ascii_case(name))
+        .map(|(_, rgb)| *rgb)
+}
+
+impl BulbModel {
+    pub fn calibration(self) -> KelvinCalibration {
+        match self {
+            BulbModel::B02Bl => KelvinCalibration::B02_BL,
+            BulbModel::B05Bl => KelvinCalibration::B05_BL,
+        }
+    }
+}
+
+impl KelvinCalibration {
+    /// Linear over the rated range of the product specifications, as
+    /// there are no measurements yet
+    pub const B02_BL: KelvinCalibration = KelvinCalibration {
+        points: &[(0, 2700), (100, 6500)],
+    };
+    /// Estimated curve over the same rated range, not measured either. Build
+    /// a `KelvinCalibration` with measured `points` for accurate results.
+    pub const B05_BL: KelvinCalibration = KelvinCalibration {
+        points: &[(0, 2700), (25, 3200), (50, 4000), (75, 5000), (100, 6500)],
+    };
+
+    pub fn min_kelvin(&self) -> u16 { self.points[0].1 }
+    pub fn max_kelvin(&self) -> u16 { self.points[self.points.len() - 1].1 }
+
+    pub fn to_ct(&self, kelvin: u16) -> Result<ColorTemp> {
+        check_range("color temperature in Kelvin", kelvin, self.min_kelvin(), self.max_kelvin())?;
+        let ct = interpolate(self.points.iter().map(|&(ct, k)| (k as f32, ct as f32)), kelvin as f32);
+        ColorTemp::try_from(ct.round() as u8)
+    }
+
+    pub fn to_kelvin(&self, ct: ColorTemp) -> u16 {
+        let kelvin = interpolate(self.points.iter().map(|&(ct, k)| (ct as f32, k as f32)), ct.get() as f32);
+        kelvin.round() as u16
+    }
+
+    pub fn to_device(&self, br: Brightness, kelvin: u16) -> Result<DevReqBulbColorTypeCW> {
+        Ok(DevReqBulbColorTypeCW { br: br.get(), ct: self.to_ct(kelvin)?.get() })
+    }
+
+    pub fn from_device(&self, cw: &DevReqBulbColorTypeCW) -> u16 {
+        self.to_kelvin(ColorTemp::saturating(cw.ct))
+    }
+}
+
+/// Piecewise linear interpolation of `x` between sorted (x, y) `points`
+fn interpolate(points: impl Iterator<Item = (f32, f32)>, x: f32) -> f32 {
+    let mut prev: Option<(f32, f32)> = None;
+    for (x1, y1) in points {
+        if let Some((x0, y0)) = prev {
+            if x <= x1 {
+                return y0 + (y1 - y0) * (x - x0) / (x1 - x0);
+            }
+        } else if x <= x1 {
+            return y1;
+        }
+        prev = Some((x1, y1));
+    }
+    prev.map_or(0.0, |(_, y)| y)
+}
+
+/// CSS Color Module Level 4 named colors
+const CSS_COLORS: &[(&str, (u8, u8, u8))] = &[
+    ("aliceblue", (240, 248, 255)),
+    ("antiquewhite", (250, 235, 215)),
+    ("aqua", (0, 255, 255)),
+    ("aquamarine", (127, 255, 212)),
+    ("azure", (240, 255, 255)),
+    ("beige", (245, 245, 220)),
+    ("bisque", (255, 228, 196)),
+    ("black", (0, 0, 0)),
+    ("blanchedalmond", (255, 235, 205)),
+    ("blue", (0, 0, 255)),
+    ("blueviolet", (138, 43, 226)),
+    ("brown", (165, 42, 42)),
+    ("burlywood", (222, 184, 135)),
+    ("cadetblue", (95, 158, 160)),
+    ("chartreuse", (127, 255, 0)),
+    ("chocolate", (210, 105, 30)),
+    ("coral", (255, 127, 80)),
+    ("cornflowerblue", (100, 149, 237)),
+    ("cornsilk", (255, 248, 220)),
+    ("crimson", (220, 20, 60)),
+    ("cyan", (0, 255, 255)),
+    ("darkblue", (0, 0, 139)),
+    ("darkcyan", (0, 139, 139)),
+    ("darkgoldenrod", (184, 134, 11)),
+    ("darkgray", (169, 169, 169)),
+    ("darkgreen", (0, 100, 0)),
+    ("darkgrey", (169, 169, 169)),
+    ("darkkhaki", (189, 183, 107)),
+    ("darkmagenta", (139, 0, 139)),
+    ("darkolivegreen", (85, 107, 47)),
+    ("darkorange", (255, 140, 0)),
+    ("darkorchid", (153, 50, 204)),
+    ("darkred", (139, 0, 0)),
+    ("darksalmon", (233, 150, 122)),
+    ("darkseagreen", (143, 188, 143)),
+    ("darkslateblue", (72, 61, 139)),
+    ("darkslategray", (47, 79, 79)),
+    ("darkslategrey", (47, 79, 79)),
+    ("darkturquoise", (0, 206, 209)),
+    ("darkviolet", (148, 0, 211)),
+    ("deeppink", (255, 20, 147)),
+    ("deepskyblue", (0, 191, 255)),
+    ("dimgray", (105, 105, 105)),
+    ("dimgrey", (105, 105, 105)),
+    ("dodgerblue", (30, 144, 255)),
+    ("firebrick", (178, 34, 34)),
+    ("floralwhite", (255, 250, 240)),
+    ("forestgreen", (34, 139, 34)),
+    ("fuchsia", (255, 0, 255)),
+    ("gainsboro", (220, 220, 220)),
+    ("ghostwhite", (248, 248, 255)),
+    ("gold", (255, 215, 0)),
+    ("goldenrod", (218, 165, 32)),
+    ("gray", (128, 128, 128)),
+    ("green", (0, 128, 0)),
+    ("greenyellow", (173, 255, 47)),
+    ("grey", (128, 128, 128)),
+    ("honeydew", (240, 255, 240)),
+    ("hotpink", (255, 105, 180)),
+    ("indianred", (205, 92, 92)),
+    ("indigo", (75, 0, 130)),
+    ("ivory", (255, 255, 240)),
+    ("khaki", (240, 230, 140)),
+    ("lavender", (230, 230, 250)),
+    ("lavenderblush", (255, 240, 245)),
+    ("lawngreen", (124, 252, 0)),
+    ("lemonchiffon", (255, 250, 205)),
+    ("lightblue", (173, 216, 230)),
+    ("lightcoral", (240, 128, 128)),
+    ("lightcyan", (224, 255, 255)),
+    ("lightgoldenrodyellow", (250, 250, 210)),
+    ("lightgray", (211, 211, 211)),
+    ("lightgreen", (144, 238, 144)),
+    ("lightgrey", (211, 211, 211)),
+    ("lightpink", (255, 182, 193)),
+    ("lightsalmon", (255, 160, 122)),
+    ("lightseagreen", (32, 178, 170)),
+    ("lightskyblue", (135, 206, 250)),
+    ("lightslategray", (119, 136, 153)),
+    ("lightslategrey", (119, 136, 153)),
+    ("lightsteelblue", (176, 196, 222)),
+    ("lightyellow", (255, 255, 224)),
+    ("lime", (0, 255, 0)),
+    ("limegreen", (50, 205, 50)),
+    ("linen", (250, 240, 230)),
+    ("magenta", (255, 0, 255)),
+    ("maroon", (128, 0, 0)),
+    ("mediumaquamarine", (102, 205, 170)),
+    ("mediumblue", (0, 0, 205)),
+    ("mediumorchid", (186, 85, 211)),
+    ("mediumpurple", (147, 112, 219)),
+    ("mediumseagreen", (60, 179, 113)),
+    ("mediumslateblue", (123, 104, 238)),
+    ("mediumspringgreen", (0, 250, 154)),
+    ("mediumturquoise", (72, 209, 204)),
+    ("mediumvioletred", (199, 21, 133)),
+    ("midnightblue", (25, 25, 112)),
+    ("mintcream", (245, 255, 250)),
+    ("mistyrose", (255, 228, 225)),
+    ("moccasin", (255, 228, 181)),
+    ("navajowhite", (255, 222, 173)),
+    ("navy", (0, 0, 128)),
+    ("oldlace", (253, 245, 230)),
+    ("olive", (128, 128, 0)),
+    ("olivedrab", (107, 142, 35)),
+    ("orange", (255, 165, 0)),
+    ("orangered", (255, 69, 0)),
+    ("orchid", (218, 112, 214)),
+    ("palegoldenrod", (238, 232, 170)),
+    ("palegreen", (152, 251, 152)),
+    ("paleturquoise", (175, 238, 238)),
+    ("palevioletred", (219, 112, 147)),
+    ("papayawhip", (255, 239, 213)),
+    ("peachpuff", (255, 218, 185)),
+    ("peru", (205, 133, 63)),
+    ("pink", (255, 192, 203)),
+    ("plum", (221, 160, 221)),
+    ("powderblue", (176, 224, 230)),
+    ("purple", (128, 0, 128)),
+    ("rebeccapurple", (102, 51, 153)),
+    ("red", (255, 0, 0)),
+    ("rosybrown", (188, 143, 143)),
+    ("royalblue", (65, 105, 225)),
+    ("saddlebrown", (139, 69, 19)),
+    ("salmon", (250, 128, 114)),
+    ("sandybrown", (244, 164, 96)),
+    ("seagreen", (46, 139, 87)),
+    ("seashell", (255, 245, 238)),
+    ("sienna", (160, 82, 45)),
+    ("silver", (192, 192, 192)),
+    ("skyblue", (135, 206, 235)),
+    ("slateblue", (106, 90, 205)),
+    ("slategray", (112, 128, 144)),
+    ("slategrey", (112, 128, 144)),
+    ("snow", (255, 250, 250)),
+    ("springgreen", (0, 255, 127)),
+    ("steelblue", (70, 130, 180)),
+    ("tan", (210, 180, 140)),
+    ("teal", (0, 128, 128)),
+    ("thistle", (216, 191, 216)),
+    ("tomato", (255, 99, 71)),
+    ("turquoise", (64, 224, 208)),
+    ("violet", (238, 130, 238)),
+    ("wheat", (245, 222, 179)),
+    ("white", (255, 255, 255)),
+    ("whitesmoke", (245, 245, 245)),
+    ("yellow", (255, 255, 0)),
+    ("yellowgreen", (154, 205, 50)),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hsv() {
+        assert_eq!(Hsv::new(120.0, 1.0, 0.5).unwrap().to_rgb(), (0, 128, 0));
+        assert_eq!(Hsv::from_rgb((255, 0, 0)), Hsv { h: 0.0, s: 1.0, v: 1.0 });
+        assert_eq!("240,100,100".parse::<Hsv>().unwrap().to_rgb(), (0, 0, 255));
+        assert!("240,100".parse::<Hsv>().is_err());
+        assert!("400,100,100".parse::<Hsv>().is_err());
+        assert!("nan,100,100".parse::<Hsv>().is_err());
+        assert!("240,inf,100".parse::<Hsv>().is_err());
+        assert!(Hsl::new(f32::NAN, 0.5, 0.5).is_err());
+        let coral = named("Coral").unwrap();
+        assert_eq!(Hsl::from_rgb(coral).to_rgb(), coral);
+        assert_eq!(Hsl::new(0.0, 1.0, 0.5).unwrap().to_rgb(), (255, 0, 0));
+    }
+
+    #[test]
+    fn test_hex() {
+        assert_eq!(parse_hex("#ff7f50").unwrap(), (255, 127, 80));
+        assert_eq!(parse_hex("00FF00").unwrap(), (0, 255, 0));
+        assert!(parse_hex("#ff7f5").is_err());
+        assert!(parse_hex("#gg0000").is_err());
+        assert_eq!(to_hex((255, 127, 80)), "#ff7f50");
+    }
+
+    #[test]
+    fn test_device() {
+        let rgb = rgb_to_device((128, 0, 0)).unwrap();
+        assert_eq!((rgb.br, rgb.r, rgb.g, rgb.b), (50, 255, 1, 1));
+        let rgb = rgb_to_device((255, 0, 255)).unwrap();
+        assert_eq!(rgb_from_device(&rgb), (255, 1, 255));
+        assert!(rgb_to_device((0, 0, 0)).is_err());
+    }
+
+    #[test]
+    fn test_kelvin() {
+        let cal = BulbModel::B05Bl.calibration();
+        assert_eq!(cal.to_ct(2700).unwrap().get(), 0);
+        assert_eq!(cal.to_ct(3600).unwrap().get(), 38);
+        assert_eq!(cal.to_ct(6500).unwrap().get(), 100);
+        assert!(cal.to_ct(2000).is_err());
+        assert_eq!(cal.to_kelvin(ColorTemp::try_from(50).unwrap()), 4000);
+        let cw = KelvinCalibration::B02_BL.to_device(Brightness::try_from(80).unwrap(), 4600).unwrap();
+        assert_eq!((cw.br, cw.ct), (80, 50));
+        assert_eq!(KelvinCalibration::B02_BL.from_device(&cw), 4600);
+    }
+}
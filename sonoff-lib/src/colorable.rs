@@ -0,0 +1,27 @@
+use async_trait::async_trait;
+
+use crate::device::DevRes;
+use crate::dimmable::SonoffDimmable;
+use crate::error::Result;
+use crate::values::{ColorTemp, Rgb};
+
+/// Devices with a color, such as color bulbs
+#[async_trait]
+pub trait SonoffColor: SonoffDimmable {
+    /// Sets the color, keeping the brightness
+    async fn set_color(&self, rgb: Rgb) -> Result<DevRes>;
+
+    /// Current color, or `None` if the device is not in color mode
+    async fn get_color(&self) -> Result<Option<Rgb>>;
+}
+
+/// Devices with a white color temperature, such as bulbs
+#[async_trait]
+pub trait SonoffColorTemp: SonoffDimmable {
+    /// Sets the color temperature, keeping the brightness
+    async fn set_color_temp(&self, ct: ColorTemp) -> Result<DevRes>;
+
+    /// Current color temperature, or `None` if the device is not in white
+    /// mode
+    async fn get_color_temp(&self) -> Result<Option<ColorTemp>>;
+}
@@ -1,8 +1,14 @@
-use anyhow::{Result, anyhow};
-use reqwest::RequestBuilder;
+use std::sync::Arc;
+
 use serde::de::DeserializeOwned;
 use serde::{Serialize, Deserialize};
 
+use crate::client::SonoffClient;
+use crate::encryption::{DeviceKey, EncryptedTransport};
+use crate::error::{Result, SonoffError};
+use crate::fade::FadeState;
+use crate::transport::{Transport, HttpTransport};
+
 // JSON models
 // ===================================================================
 
@@ -26,26 +32,46 @@ pub struct DevRes {
 #[derive(Clone)]
 pub struct SonoffDevice {
     pub id: String,
-    pub address: String,
+    transport: Arc<dyn Transport>,
+    /// Held during read-modify-write sequences (e.g. dimming a bulb), shared
+    /// by every clone of the handle
+    pub(crate) state_lock: Arc<tokio::sync::Mutex<()>>,
+    pub(crate) fade: Arc<FadeState>,
 }
 
 impl SonoffDevice {
 
+    /// Handle with its own client. Use `SonoffClient::device` to share a
+    /// client between many devices.
     pub fn new(address: impl Into<String>) -> SonoffDevice {
+        SonoffDevice::with_client(SonoffClient::default(), address)
+    }
+
+    pub fn with_client(client: SonoffClient, address: impl Into<String>) -> SonoffDevice {
+        SonoffDevice::with_transport(HttpTransport::new(client, address))
+    }
+
+    pub fn with_transport(transport: impl Transport + 'static) -> SonoffDevice {
         SonoffDevice {
             id: "".to_owned(),
-            address: address.into(),
+            transport: Arc::new(transport),
+            state_lock: Arc::default(),
+            fade: Arc::default(),
         }
     }
 
-    fn post(&self, url_path: impl AsRef<str>) -> Result<RequestBuilder> {
-        let mut url = self.address.to_owned();
-        let url_path_str = url_path.as_ref();
-        url.push_str(&format!("/zeroconf{url_path_str}"));
-        let client = reqwest::Client::builder()
-            .http1_title_case_headers()
-            .build()?;
-        Ok(client.post(url))
+    /// Base address of the device (e.g. "http://192.168.1.2:8081"), unless
+    /// it uses a transport without one (e.g. `MockTransport`)
+    pub fn address(&self) -> Option<&str> {
+        self.transport.address()
+    }
+
+    /// Switches to eWeLink LAN mode, used by devices not in DIY mode.
+    /// Requests and responses are encrypted with `device_key`.
+    pub fn with_device_key(mut self, device_key: impl AsRef<str>) -> SonoffDevice {
+        let key = DeviceKey::new(device_key);
+        self.transport = Arc::new(EncryptedTransport::new(self.transport, key));
+        self
     }
 
     pub async fn __request<Treq>(&self, url_path: impl AsRef<str>, req_type: Treq) -> Result<DevRes>
@@ -56,13 +82,11 @@ impl SonoffDevice {
             device_id: self.id.to_owned(),
             data: serde_json::to_value(req_type)?,
         };
-        //println!("{}", serde_json::to_string(&req_obj)?); // TODO: for debugging
-        let res = self.post(url_path)?.body(serde_json::to_string(&req_obj)?)
-            .send().await?
-            .error_for_status()? // Remove when debugging
-            .text().await?;
-        //println!("{}", res);  // TODO: for debugging
-        let dev_res: DevRes = serde_json::from_str(&res)?;
+        let res = self.transport.send(url_path.as_ref(), serde_json::to_value(&req_obj)?).await?;
+        let dev_res: DevRes = serde_json::from_value(res)?;
+        if dev_res.error != 0 {
+            return Err(SonoffError::Device { code: dev_res.error.into(), seq: dev_res.seq });
+        }
         Ok(dev_res)
     }
 
@@ -73,7 +97,7 @@ impl SonoffDevice {
     {
         let dev_res = self.__request(url_path, req_type).await?;
         let Some(data) = dev_res.data else {
-            return Err(anyhow!("Bad response from device"))
+            return Err(SonoffError::BadResponse)
         };
         Ok(serde_json::from_value(data)?)
     }
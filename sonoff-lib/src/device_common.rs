@@ -1,7 +1,8 @@
-use anyhow::Result;
 use serde::{Serialize, Deserialize};
 
 use crate::device::{SonoffDevice, DevRes};
+use crate::error::{Result, SonoffError};
+use crate::switchable::SwitchState;
 
 // JSON models
 // ===================================================================
@@ -24,8 +25,25 @@ pub struct UnlockOTAReq { }
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct UpdateOTAReq {
-    download_url: String,
-    sha256sum: String,
+    pub download_url: String,
+    pub sha256sum: String,
+}
+
+/// Network indicator LED
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LedReq {
+    pub sled_online: SwitchState,
+}
+
+/// State endpoint of devices without LED state in `/info` (e.g. SPM-MAIN)
+#[derive(Debug, Serialize)]
+pub struct DevStateReq { }
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DevLedState {
+    pub sled_online: Option<SwitchState>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -37,6 +55,8 @@ pub struct DevInfo {
     pub signal_strength: Option<i32>,
     pub fw_version: Option<String>,
     pub ota_unlock: Option<bool>,
+    /// Network indicator LED, missing on devices without one (e.g. bulbs)
+    pub sled_online: Option<SwitchState>,
     #[serde(flatten)]
     pub per_device_info: serde_json::Value,
 }
@@ -54,16 +74,75 @@ impl SonoffDevice {
         let req_obj = WifiSetupReq { ssid, password, };
         self.__request("/wifi".to_owned(), req_obj).await
     }
+
+    /// Turns the network indicator LED on or off. It still blinks while the
+    /// device is not connected to Wi-Fi.
+    pub async fn set_led(&self, on: bool) -> Result<DevRes> {
+        let req_obj = LedReq { sled_online: on.into() };
+        self.__request("/sledonoff", req_obj).await
+    }
+
+    /// Whether the network indicator LED is on
+    pub async fn get_led(&self) -> Result<bool> {
+        if let Some(sled_online) = self.get_info().await?.sled_online {
+            return Ok(sled_online.into());
+        }
+        let state: DevLedState = self.request("/getState", DevStateReq {}).await?;
+        state.sled_online.map(bool::from).ok_or(SonoffError::BadResponse)
+    }
+
+    /// Allows flashing firmware over the air (see `flash_ota`). The device
+    /// must be able to reach the eWeLink cloud.
+    pub async fn unlock_ota(&self) -> Result<DevRes> {
+        let req_obj = UnlockOTAReq {};
+        self.__request("/ota_unlock", req_obj).await
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::sync::Arc;
+    use serde_json::json;
     use super::*;
+    use crate::error::{DevErrorCode, SonoffError};
+    use crate::transport::MockTransport;
 
     #[tokio::test]
     async fn test_get_info() {
-        let dev = SonoffDevice::new("http://127.0.0.1:8081");
+        let dev = SonoffDevice::with_transport(MockTransport::with_data(json!({
+            "deviceid": "1000abcdef",
+            "ssid": "home",
+            "signalStrength": -60,
+            "fwVersion": "3.6.0",
+            "switch": "on",
+        })));
         let dev_info = dev.get_info().await.unwrap();
-        println!("{}", serde_json::to_string_pretty(&dev_info).unwrap());
+        assert_eq!(dev_info.deviceid, "1000abcdef");
+        assert_eq!(dev_info.signal_strength, Some(-60));
+        assert_eq!(dev_info.fw_version.as_deref(), Some("3.6.0"));
+        assert_eq!(dev_info.per_device_info["switch"], "on");
+    }
+
+    #[tokio::test]
+    async fn test_led() {
+        let mock = Arc::new(MockTransport::new(|url_path, _| match url_path {
+            "/info" => json!({ "seq": 1, "error": 0, "data": { "deviceid": "1000abcdef" } }),
+            "/getState" => json!({ "seq": 1, "error": 0, "data": { "deviceid": "1000abcdef", "sledOnline": "off" } }),
+            _ => json!({ "seq": 2, "error": 0 }),
+        }));
+        let dev = SonoffDevice::with_transport(mock.clone());
+        dev.set_led(false).await.unwrap();
+        assert_eq!(mock.requests()[0].1["data"], json!({ "sledOnline": "off" }));
+        assert!(!dev.get_led().await.unwrap());
+        assert_eq!(mock.requests().last().unwrap().0, "/getState");
+    }
+
+    #[tokio::test]
+    async fn test_device_error() {
+        let dev = SonoffDevice::with_transport(MockTransport::new(|_, _| {
+            json!({ "seq": 2, "error": 422 })
+        }));
+        let err = dev.set_wifi("home".to_owned(), "secret".to_owned()).await.unwrap_err();
+        assert!(matches!(err, SonoffError::Device { code: DevErrorCode::InvalidParams, seq: 2 }));
     }
 }
@@ -1,11 +1,30 @@
-use anyhow::{Result};
+use std::time::Duration;
 
 use async_trait::async_trait;
 
 use crate::device::DevRes;
+use crate::error::Result;
+use crate::fade::{FadeCurve, FadeOutcome};
+use crate::switchable::SonoffSwitchable;
+use crate::values::Brightness;
 
 #[async_trait]
-pub trait SonoffDimmable {
-    /// brightness (min=0, max=100)
-    async fn dim(&self, br: u8) -> Result<DevRes>;
+pub trait SonoffDimmable: SonoffSwitchable {
+    async fn dim(&self, br: Brightness) -> Result<DevRes>;
+
+    async fn get_brightness(&self) -> Result<Brightness>;
+
+    /// Fades from the current brightness to `target` over `duration`.
+    ///
+    /// A new fade on the same device replaces the one in progress, which
+    /// then returns `FadeOutcome::Cancelled`. Dropping the future also stops
+    /// the fade.
+    async fn fade_to(&self, target: Brightness, duration: Duration, curve: FadeCurve) -> Result<FadeOutcome> {
+        crate::fade::fade(self, target, duration, curve).await
+    }
+
+    /// Stops the fade in progress on the device, if any
+    fn cancel_fade(&self) {
+        self.get_dev().fade.cancel();
+    }
 }
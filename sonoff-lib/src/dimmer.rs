@@ -1,33 +1,52 @@
-use anyhow::{Result};
 use async_trait::async_trait;
 use serde::{Serialize, Deserialize};
 
 use crate::device::{SonoffDevice, DevRes};
+use crate::error::{Result, SonoffError};
 use crate::dimmable::SonoffDimmable;
-use crate::switchable::SonoffSwitchable;
+use crate::switchable::{SonoffSwitchable, SonoffSwitchReq, SwitchState, StartupState};
+use crate::values::{check_range, Brightness};
 
 // JSON models
 // ===================================================================
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct DevInfoDataDimmer {
-    pub switch: String,
-    pub startup: String,
+    pub switch: SwitchState,
+    pub startup: StartupState,
     pub brightness: u8,
+    /// Dimming mode (see `DimmerMode`)
     pub mode: u8,
+    /// Output level for brightness 1 (min=0, max=100)
     pub brightmin: u8,
+    /// Output level for brightness 100 (min=0, max=100)
     pub brightmax: u8,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct DevReqDimmer {
-    pub switch: String,
+    pub switch: SwitchState,
     pub brightness: u8,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub mode: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub brightmin: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub brightmax: Option<u8>,
 }
 
+/// How the dimmer cuts the mains phase
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+pub enum DimmerMode {
+    /// Pick the edge from the connected load
+    Auto,
+    /// Leading edge, for inductive loads
+    LeadingEdge,
+    /// Trailing edge, for capacitive loads such as most LED drivers
+    TrailingEdge,
+}
+
 // Implementation
 // ===================================================================
 
@@ -41,41 +60,168 @@ impl From<&SonoffDevice> for SonoffDimmer {
     }
 }
 
+impl From<DimmerMode> for u8 {
+    fn from(value: DimmerMode) -> Self {
+        match value {
+            DimmerMode::Auto => 0,
+            DimmerMode::LeadingEdge => 1,
+            DimmerMode::TrailingEdge => 2,
+        }
+    }
+}
+
+impl TryFrom<u8> for DimmerMode {
+    type Error = SonoffError;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(DimmerMode::Auto),
+            1 => Ok(DimmerMode::LeadingEdge),
+            2 => Ok(DimmerMode::TrailingEdge),
+            _ => Err(SonoffError::InvalidValue(format!("dimming mode must be between 0 and 2, got {value}"))),
+        }
+    }
+}
+
+impl std::fmt::Display for DimmerMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            DimmerMode::Auto => "auto",
+            DimmerMode::LeadingEdge => "leading-edge",
+            DimmerMode::TrailingEdge => "trailing-edge",
+        };
+        write!(f, "{name}")
+    }
+}
+
 impl SonoffDimmer {
     pub async fn get_info(&self) -> Result<DevInfoDataDimmer> {
         let info = self.dev.get_info().await?;
         Ok(serde_json::from_value(info.per_device_info)?)
     }
+
+    /// Sets the dimming mode
+    pub async fn set_mode(&self, mode: DimmerMode) -> Result<DevRes> {
+        self.configure(Some(mode), None, None).await
+    }
+
+    /// Sets the output levels (min=0, max=100) that brightness 1 and 100
+    /// map to. Raising `brightmin` stops LED loads from flickering at low
+    /// brightness.
+    pub async fn set_calibration(&self, brightmin: u8, brightmax: u8) -> Result<DevRes> {
+        self.configure(None, Some(brightmin), Some(brightmax)).await
+    }
+
+    /// Changes the mode and calibration, keeping the switch state and
+    /// brightness. `None` keeps the current value.
+    pub async fn configure(&self, mode: Option<DimmerMode>, brightmin: Option<u8>, brightmax: Option<u8>) -> Result<DevRes> {
+        // The switch state and brightness must be sent too
+        let _guard = self.dev.state_lock.lock().await;
+        let info = self.get_info().await?;
+        let min = check_range("brightmin", brightmin.unwrap_or(info.brightmin), 0, 100)?;
+        let max = check_range("brightmax", brightmax.unwrap_or(info.brightmax), 0, 100)?;
+        if min >= max {
+            return Err(SonoffError::InvalidValue(format!("brightmin ({min}) must be lower than brightmax ({max})")));
+        }
+        let req_obj = DevReqDimmer {
+            switch: info.switch,
+            brightness: info.brightness,
+            mode: mode.map(u8::from),
+            brightmin,
+            brightmax,
+        };
+        self.dev.__request("/dimmable".to_owned(), req_obj).await
+    }
 }
 
 #[async_trait]
 impl SonoffSwitchable for SonoffDimmer {
     fn get_dev(&self) -> &SonoffDevice { &self.dev }
+
+    // Holds the lock so that `configure` does not send back a stale state
+    async fn set_switch(&self, state: SwitchState) -> Result<DevRes> {
+        let _guard = self.dev.state_lock.lock().await;
+        let req_obj = SonoffSwitchReq { switch: state };
+        self.dev.__request("/switch".to_owned(), req_obj).await
+    }
 }
 
 #[async_trait]
 impl SonoffDimmable for SonoffDimmer {
-    async fn dim(&self, br: u8) -> Result<DevRes> {
+    async fn dim(&self, br: Brightness) -> Result<DevRes> {
+        let _guard = self.dev.state_lock.lock().await;
         let req_obj = DevReqDimmer {
-            switch: "on".to_owned(), // must be "on"
-            brightness: br,
+            switch: SwitchState::On, // must be "on"
+            brightness: br.get(),
             mode: None,
             brightmin: None,
             brightmax: None,
         };
-        self.dev.request("/dimmable".to_owned(), req_obj).await
+        self.dev.__request("/dimmable".to_owned(), req_obj).await
+    }
+
+    async fn get_brightness(&self) -> Result<Brightness> {
+        let info = self.get_info().await?;
+        Ok(Brightness::saturating(info.brightness))
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::sync::Arc;
+    use serde_json::json;
     use super::*;
+    use crate::transport::MockTransport;
 
     #[tokio::test]
     async fn test_dimmer_half() {
-        let dev = SonoffDevice::new("http://127.0.0.1:8081");
+        let mock = Arc::new(MockTransport::with_data(json!({})));
+        let dev = SonoffDevice::with_transport(mock.clone());
         let dimmer = SonoffDimmer::from(&dev);
         dimmer.on().await.unwrap();
-        dimmer.dim(50).await.unwrap();
+        dimmer.dim(Brightness::try_from(50).unwrap()).await.unwrap();
+        let requests = mock.requests();
+        assert_eq!(requests[1].0, "/dimmable");
+        assert_eq!(requests[1].1["data"]["brightness"], 50);
+        assert!(requests[1].1["data"].get("mode").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_dimmer_reply_without_data() {
+        // The D1 acknowledges /dimmable without a `data` object
+        let mock = Arc::new(MockTransport::new(|_, _| json!({ "seq": 7, "error": 0 })));
+        let dimmer = SonoffDimmer::from(&SonoffDevice::with_transport(mock));
+        let res = dimmer.dim(Brightness::try_from(50).unwrap()).await.unwrap();
+        assert_eq!(res.seq, 7);
+    }
+
+    #[tokio::test]
+    async fn test_dimmer_calibration() {
+        let mock = Arc::new(MockTransport::new(|url_path, _| match url_path {
+            "/info" => json!({ "seq": 1, "error": 0, "data": {
+                "deviceid": "1000abcdef",
+                "switch": "off",
+                "startup": "off",
+                "brightness": 30,
+                "mode": 0,
+                "brightmin": 0,
+                "brightmax": 100,
+            }}),
+            _ => json!({ "seq": 2, "error": 0 }),
+        }));
+        let dev = SonoffDevice::with_transport(mock.clone());
+        let dimmer = SonoffDimmer::from(&dev);
+        dimmer.set_calibration(15, 90).await.unwrap();
+        assert_eq!(mock.requests()[1].1["data"], json!({
+            "switch": "off",
+            "brightness": 30,
+            "brightmin": 15,
+            "brightmax": 90,
+        }));
+        let err = dimmer.set_calibration(60, 40).await.unwrap_err();
+        assert!(matches!(err, SonoffError::InvalidValue(_)));
+        dimmer.set_mode(DimmerMode::TrailingEdge).await.unwrap();
+        assert_eq!(mock.requests()[4].1["data"]["mode"], 2);
+        assert!(DimmerMode::try_from(3).is_err());
     }
 }
@@ -0,0 +1,203 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::Duration;
+
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+
+use crate::client::SonoffClient;
+use crate::device::SonoffDevice;
+use crate::encryption::DeviceKey;
+use crate::error::{Result, SonoffError};
+
+/// mDNS service type announced by devices in DIY mode
+pub const SERVICE_TYPE: &str = "_ewelink._tcp.local.";
+
+// Models
+// ===================================================================
+
+/// Device announced over mDNS, with its TXT record already parsed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiscoveredDevice {
+    /// Device ID (e.g. "1000abcdef")
+    pub id: String,
+    /// Device type (e.g. "diy_plug", "strip", "light", "dimmable")
+    pub device_type: String,
+    pub apivers: Option<u32>,
+    pub txtvers: Option<u32>,
+    /// Incremented by the device every time its state changes
+    pub seq: Option<u32>,
+    /// State of the device, as announced in `data1` to `data4`. Base64
+    /// ciphertext if `encrypted`.
+    pub data: String,
+    /// Whether the device is in eWeLink LAN mode (see `decrypted`)
+    pub encrypted: bool,
+    /// Base64 IV of `data`, if `encrypted`
+    pub iv: Option<String>,
+    pub addresses: Vec<IpAddr>,
+    pub port: u16,
+}
+
+impl DiscoveredDevice {
+    /// Parses the TXT record of a device. Returns `None` if the record has
+    /// no `id` or `addresses` is empty.
+    pub fn from_txt(txt: &HashMap<String, String>, addresses: Vec<IpAddr>, port: u16) -> Option<DiscoveredDevice> {
+        if addresses.is_empty() {
+            return None;
+        }
+        let number = |key: &str| txt.get(key).and_then(|v| v.parse().ok());
+        // Long states are split across data1, data2, data3, and data4
+        let data = (1..=4)
+            .filter_map(|i| txt.get(&format!("data{i}")))
+            .map(String::as_str)
+            .collect();
+        Some(DiscoveredDevice {
+            id: txt.get("id")?.to_owned(),
+            device_type: txt.get("type").cloned().unwrap_or_default(),
+            apivers: number("apivers"),
+            txtvers: number("txtvers"),
+            seq: number("seq"),
+            data,
+            encrypted: txt.get("encrypt").is_some_and(|v| v == "true"),
+            iv: txt.get("iv").cloned(),
+            addresses,
+            port,
+        })
+    }
+
+    pub(crate) fn from_service_info(info: &ServiceInfo) -> Option<DiscoveredDevice> {
+        let txt = info.get_properties().iter()
+            .map(|p| (p.key().to_owned(), p.val_str().to_owned()))
+            .collect();
+        let mut addresses: Vec<IpAddr> = info.get_addresses().iter().copied().collect();
+        // Prefer IPv4, which all devices support
+        addresses.sort_by_key(|addr| (addr.is_ipv6(), *addr));
+        DiscoveredDevice::from_txt(&txt, addresses, info.get_port())
+    }
+
+    /// Base address of the device (e.g. "http://192.168.1.2:8081"), using
+    /// the first of `addresses`. `None` if `addresses` is empty.
+    pub fn address(&self) -> Option<String> {
+        match self.addresses.first()? {
+            IpAddr::V4(ip) => Some(format!("http://{}:{}", ip, self.port)),
+            IpAddr::V6(ip) => Some(format!("http://[{}]:{}", ip, self.port)),
+        }
+    }
+
+    /// State of the device (`data1` to `data4`) as JSON
+    pub fn data_json(&self) -> Result<serde_json::Value> {
+        Ok(serde_json::from_str(&self.data)?)
+    }
+
+    /// Copy of the announcement with `data` decrypted with `device_key`.
+    /// Unencrypted announcements are copied as they are.
+    pub fn decrypted(&self, device_key: impl AsRef<str>) -> Result<DiscoveredDevice> {
+        let mut found = self.to_owned();
+        if self.encrypted {
+            let iv = self.iv.as_deref().ok_or(SonoffError::Decryption)?;
+            let plaintext = DeviceKey::new(device_key).decrypt(iv, &self.data)?;
+            found.data = String::from_utf8(plaintext).map_err(|_| SonoffError::Decryption)?;
+            found.encrypted = false;
+            found.iv = None;
+        }
+        Ok(found)
+    }
+
+    /// Handle for the device, with `id` already set. Encrypted devices also
+    /// need `SonoffDevice::with_device_key`. `None` if `addresses` is empty.
+    pub fn device(&self, client: &SonoffClient) -> Option<SonoffDevice> {
+        let mut dev = client.device(self.address()?);
+        dev.id = self.id.to_owned();
+        Some(dev)
+    }
+}
+
+// Implementation
+// ===================================================================
+
+/// Browses the local network for `timeout` and returns every device that
+/// announced itself, once per device ID. Fails if the mDNS daemon stops
+/// before `timeout`.
+pub async fn discover(timeout: Duration) -> Result<Vec<DiscoveredDevice>> {
+    let mdns = ServiceDaemon::new()?;
+    let receiver = mdns.browse(SERVICE_TYPE)?;
+    let mut devices: Vec<DiscoveredDevice> = Vec::new();
+    let mut stopped = None;
+    let _ = tokio::time::timeout(timeout, async {
+        loop {
+            let event = match receiver.recv_async().await {
+                Ok(event) => event,
+                Err(err) => {
+                    stopped = Some(err);
+                    break;
+                },
+            };
+            let ServiceEvent::ServiceResolved(info) = event else {
+                continue;
+            };
+            let Some(found) = DiscoveredDevice::from_service_info(&info) else {
+                continue;
+            };
+            match devices.iter_mut().find(|dev| dev.id == found.id) {
+                Some(dev) => *dev = found,
+                None => devices.push(found),
+            }
+        }
+    }).await;
+    mdns.shutdown()?;
+    if let Some(err) = stopped {
+        return Err(mdns_sd::Error::Msg(format!("browsing stopped: {err}")).into());
+    }
+    Ok(devices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_txt() {
+        let txt: HashMap<String, String> = [
+            ("id", "1000abcdef"),
+            ("type", "diy_plug"),
+            ("apivers", "1"),
+            ("txtvers", "1"),
+            ("seq", "42"),
+            ("data1", r#"{"switch":"on","startup":"stay","#),
+            ("data2", r#""pulse":"off","pulseWidth":500}"#),
+        ].into_iter().map(|(k, v)| (k.to_owned(), v.to_owned())).collect();
+        let addresses = vec!["192.168.1.2".parse().unwrap()];
+        let found = DiscoveredDevice::from_txt(&txt, addresses, 8081).unwrap();
+        assert_eq!(found.id, "1000abcdef");
+        assert_eq!(found.device_type, "diy_plug");
+        assert_eq!(found.seq, Some(42));
+        assert_eq!(found.address().as_deref(), Some("http://192.168.1.2:8081"));
+        assert_eq!(found.data_json().unwrap()["startup"], "stay");
+        let dev = found.device(&SonoffClient::default()).unwrap();
+        assert_eq!(dev.id, "1000abcdef");
+
+        let mut no_address = found.clone();
+        no_address.addresses.clear();
+        assert_eq!(no_address.address(), None);
+        assert!(no_address.device(&SonoffClient::default()).is_none());
+    }
+
+    #[test]
+    fn test_decrypted() {
+        let device_key = "2b3d4f5e-aaaa-bbbb-cccc-0123456789ab";
+        let (iv, data) = DeviceKey::new(device_key).encrypt(br#"{"switch":"on"}"#);
+        let (data1, data2) = data.split_at(10);
+        let txt: HashMap<String, String> = [
+            ("id", "1000abcdef"),
+            ("type", "plug"),
+            ("encrypt", "true"),
+            ("iv", iv.as_str()),
+            ("data1", data1),
+            ("data2", data2),
+        ].into_iter().map(|(k, v)| (k.to_owned(), v.to_owned())).collect();
+        let addresses = vec!["192.168.1.2".parse().unwrap()];
+        let found = DiscoveredDevice::from_txt(&txt, addresses, 8081).unwrap();
+        assert!(found.encrypted);
+        let found = found.decrypted(device_key).unwrap();
+        assert_eq!(found.data_json().unwrap()["switch"], "on");
+    }
+}
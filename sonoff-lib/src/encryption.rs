@@ -0,0 +1,165 @@
+//! eWeLink LAN mode: devices not in DIY mode encrypt the `data` of every
+//! request and response with AES-128-CBC. The key is the MD5 digest of the
+//! device key (the "devicekey" obtained when pairing the device with the
+//! eWeLink app) and the IV is random for every message.
+
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use aes::cipher::block_padding::Pkcs7;
+use aes::cipher::{BlockDecryptMut, BlockEncryptMut, KeyIvInit};
+use async_trait::async_trait;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use md5::{Digest, Md5};
+use serde::{Serialize, Deserialize};
+
+use crate::error::{Result, SonoffError};
+use crate::transport::Transport;
+
+type Aes128CbcEnc = cbc::Encryptor<aes::Aes128>;
+type Aes128CbcDec = cbc::Decryptor<aes::Aes128>;
+
+// JSON models
+// ===================================================================
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EncryptedDevReq {
+    pub sequence: String,
+    pub deviceid: String,
+    pub self_apikey: String,
+    /// Base64 IV
+    pub iv: String,
+    pub encrypt: bool,
+    /// Base64 encrypted JSON of the request data
+    pub data: String,
+}
+
+// Implementation
+// ===================================================================
+
+/// AES-128 key derived from a device key
+#[derive(Clone)]
+pub struct DeviceKey([u8; 16]);
+
+impl DeviceKey {
+    pub fn new(device_key: impl AsRef<str>) -> DeviceKey {
+        DeviceKey(Md5::digest(device_key.as_ref().as_bytes()).into())
+    }
+
+    /// Encrypts `plaintext` with a random IV. Returns (IV, ciphertext), both
+    /// in base64.
+    pub fn encrypt(&self, plaintext: &[u8]) -> (String, String) {
+        let iv: [u8; 16] = rand::random();
+        let ciphertext = Aes128CbcEnc::new(&self.0.into(), &iv.into())
+            .encrypt_padded_vec_mut::<Pkcs7>(plaintext);
+        (BASE64.encode(iv), BASE64.encode(ciphertext))
+    }
+
+    /// Decrypts base64 `ciphertext` with base64 `iv`
+    pub fn decrypt(&self, iv: &str, ciphertext: &str) -> Result<Vec<u8>> {
+        let iv: [u8; 16] = BASE64.decode(iv.trim())
+            .map_err(|_| SonoffError::Decryption)?
+            .try_into()
+            .map_err(|_| SonoffError::Decryption)?;
+        let ciphertext = BASE64.decode(ciphertext.trim())
+            .map_err(|_| SonoffError::Decryption)?;
+        Aes128CbcDec::new(&self.0.into(), &iv.into())
+            .decrypt_padded_vec_mut::<Pkcs7>(&ciphertext)
+            .map_err(|_| SonoffError::Decryption)
+    }
+}
+
+/// Wraps another transport and encrypts the `data` of requests and
+/// decrypts the `data` of responses.
+pub struct EncryptedTransport {
+    inner: Arc<dyn Transport>,
+    key: DeviceKey,
+}
+
+impl EncryptedTransport {
+    pub fn new(inner: Arc<dyn Transport>, key: DeviceKey) -> EncryptedTransport {
+        EncryptedTransport { inner, key }
+    }
+}
+
+#[async_trait]
+impl Transport for EncryptedTransport {
+    async fn send(&self, url_path: &str, body: serde_json::Value) -> Result<serde_json::Value> {
+        let (iv, data) = self.key.encrypt(serde_json::to_string(&body["data"])?.as_bytes());
+        let sequence = SystemTime::now().duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis()
+            .to_string();
+        let req_obj = EncryptedDevReq {
+            sequence,
+            deviceid: body["deviceId"].as_str().unwrap_or_default().to_owned(),
+            self_apikey: "123".to_owned(), // any value is accepted
+            iv,
+            encrypt: true,
+            data,
+        };
+        let mut res = self.inner.send(url_path, serde_json::to_value(req_obj)?).await?;
+        // Errors are reported without data, and are left to the caller
+        if res["encrypt"] == true && res["error"] == 0 {
+            let (Some(iv), Some(data)) = (res["iv"].as_str(), res["data"].as_str()) else {
+                return Err(SonoffError::Decryption);
+            };
+            let plaintext = self.key.decrypt(iv, data)?;
+            res["data"] = serde_json::from_slice(&plaintext)?;
+        }
+        Ok(res)
+    }
+
+    fn address(&self) -> Option<&str> { self.inner.address() }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use super::*;
+    use crate::device::SonoffDevice;
+    use crate::switch::SonoffSwitch;
+    use crate::switchable::SonoffSwitchable;
+    use crate::transport::MockTransport;
+
+    #[test]
+    fn test_roundtrip() {
+        let key = DeviceKey::new("2b3d4f5e-aaaa-bbbb-cccc-0123456789ab");
+        let (iv, ciphertext) = key.encrypt(br#"{"switch":"on"}"#);
+        assert_eq!(key.decrypt(&iv, &ciphertext).unwrap(), br#"{"switch":"on"}"#);
+        let other = DeviceKey::new("another key");
+        assert!(other.decrypt(&iv, &ciphertext).map_or(true, |p| p != br#"{"switch":"on"}"#));
+    }
+
+    #[tokio::test]
+    async fn test_encrypted_device() {
+        let device_key = "2b3d4f5e-aaaa-bbbb-cccc-0123456789ab";
+        let key = DeviceKey::new(device_key);
+        let mock = MockTransport::new(move |url_path, body| {
+            assert_eq!(url_path, "/info");
+            assert_eq!(body["encrypt"], true);
+            let req = key.decrypt(body["iv"].as_str().unwrap(), body["data"].as_str().unwrap()).unwrap();
+            assert_eq!(serde_json::from_slice::<serde_json::Value>(&req).unwrap(), json!({}));
+            let (iv, data) = key.encrypt(br#"{"switch":"on"}"#);
+            json!({ "seq": 1, "error": 0, "encrypt": true, "iv": iv, "data": data })
+        });
+        let dev = SonoffDevice::with_transport(mock).with_device_key(device_key);
+        assert!(SonoffSwitch::from(&dev).get_switch().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_encrypted_reply_without_data() {
+        let device_key = "2b3d4f5e-aaaa-bbbb-cccc-0123456789ab";
+        let mock = MockTransport::new(|_, _| json!({ "seq": 1, "error": 0, "encrypt": true }));
+        let dev = SonoffDevice::with_transport(mock).with_device_key(device_key);
+        let err = dev.__request("/switch", json!({ "switch": "on" })).await.unwrap_err();
+        assert!(matches!(err, SonoffError::Decryption));
+
+        let mock = MockTransport::new(|_, _| json!({ "seq": 1, "error": 401, "encrypt": true }));
+        let dev = SonoffDevice::with_transport(mock).with_device_key(device_key);
+        let err = dev.__request("/switch", json!({ "switch": "on" })).await.unwrap_err();
+        assert!(matches!(err, SonoffError::Device { .. }));
+    }
+}
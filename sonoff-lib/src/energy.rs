@@ -0,0 +1,218 @@
+//! SPM energy history: the SPM-MAIN stores the hourly energy and a log of
+//! readings of every channel of its sub-devices for `HISTORY_MAX`.
+//!
+//! Experimental: `/getHoursKwh` and `/getDataLog` are not part of the
+//! published DIY mode API. Their fields and the hundredths scaling follow
+//! the other SPM endpoints (see `PowerMeterPVC`) and may change once
+//! checked against a device.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Result, SonoffError};
+use crate::power_meter::{PowerReading, SonoffPowerMeter, SonoffSubDevice, SPM_OUTLETS};
+use crate::values::check_range;
+
+/// How far back the device keeps history
+pub const HISTORY_MAX: Duration = Duration::from_secs(30 * 24 * 3600);
+
+// JSON models
+// ===================================================================
+
+/// Time range of a history request, in Unix time (seconds)
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SPMHistoryReq {
+    pub sub_dev_id: String,
+    pub outlet: u8,
+    pub start: u64,
+    pub end: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SPMHoursKwh {
+    /// Unix time of the first hour
+    pub start: u64,
+    /// Energy of every hour from `start`, in hundredths of kWh
+    pub kwh: Vec<u32>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SPMDataLog {
+    pub data_log: Vec<DevDataLogEntry>,
+}
+
+/// Logged readings, in hundredths of A, V, W, var and VA
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DevDataLogEntry {
+    /// Unix time
+    pub time: u64,
+    pub current: u32,
+    pub voltage: u32,
+    pub act_pow: u32,
+    pub react_pow: u32,
+    pub apparent_pow: u32,
+}
+
+/// Energy used by a channel during one hour
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct HourlyEnergy {
+    /// Unix time of the start of the hour
+    pub timestamp: u64,
+    pub kwh: f64,
+}
+
+/// Readings of a channel logged by the device
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct LoggedReading {
+    /// Unix time
+    pub timestamp: u64,
+    #[serde(flatten)]
+    pub reading: PowerReading,
+}
+
+// Implementation
+// ===================================================================
+
+/// Energy is sent in hundredths of kWh
+const KWH_SCALE: f64 = 0.01;
+const HOUR: u64 = 3600;
+
+fn unix_time(time: SystemTime) -> Result<u64> {
+    time.duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .map_err(|_| SonoffError::InvalidValue("time must be after 1970-01-01".to_owned()))
+}
+
+impl HourlyEnergy {
+    pub fn start(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(self.timestamp)
+    }
+}
+
+impl LoggedReading {
+    pub fn time(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(self.timestamp)
+    }
+}
+
+/// Total of a series of hourly energy, in kWh
+pub fn total_kwh(hours: &[HourlyEnergy]) -> f64 {
+    hours.iter().map(|hour| hour.kwh).sum()
+}
+
+impl SonoffPowerMeter {
+    fn history_req(&self, sub_dev_id: String, outlet: u8, start: SystemTime, end: SystemTime) -> Result<SPMHistoryReq> {
+        let outlet = check_range("outlet", outlet, 0, SPM_OUTLETS - 1)?;
+        let (start, end) = (unix_time(start)?, unix_time(end)?);
+        if start >= end {
+            return Err(SonoffError::InvalidValue("start must be before end".to_owned()));
+        }
+        if end - start > HISTORY_MAX.as_secs() {
+            return Err(SonoffError::InvalidValue(format!("history spans at most {} days", HISTORY_MAX.as_secs() / (24 * HOUR))));
+        }
+        Ok(SPMHistoryReq { sub_dev_id, outlet, start, end })
+    }
+
+    /// Energy used by `outlet` (min=0, max=3) of a sub-device every hour
+    /// from `start` to `end`
+    pub async fn energy_history(
+        &self,
+        sub_dev_id: String,
+        outlet: u8,
+        start: SystemTime,
+        end: SystemTime,
+    ) -> Result<Vec<HourlyEnergy>> {
+        let req_obj = self.history_req(sub_dev_id, outlet, start, end)?;
+        let res: SPMHoursKwh = self.get_dev().request("/getHoursKwh", req_obj).await?;
+        Ok(res.kwh.iter().enumerate()
+            .map(|(i, &kwh)| HourlyEnergy { timestamp: res.start + i as u64 * HOUR, kwh: kwh as f64 * KWH_SCALE })
+            .collect())
+    }
+
+    /// Readings of `outlet` (min=0, max=3) of a sub-device logged from
+    /// `start` to `end`
+    pub async fn data_log(
+        &self,
+        sub_dev_id: String,
+        outlet: u8,
+        start: SystemTime,
+        end: SystemTime,
+    ) -> Result<Vec<LoggedReading>> {
+        let req_obj = self.history_req(sub_dev_id, outlet, start, end)?;
+        let res: SPMDataLog = self.get_dev().request("/getDataLog", req_obj).await?;
+        Ok(res.data_log.iter()
+            .map(|entry| LoggedReading {
+                timestamp: entry.time,
+                reading: PowerReading::new(outlet, entry.current, entry.voltage, entry.act_pow, entry.react_pow, entry.apparent_pow),
+            })
+            .collect())
+    }
+}
+
+impl SonoffSubDevice {
+    pub async fn energy_history(&self, outlet: u8, start: SystemTime, end: SystemTime) -> Result<Vec<HourlyEnergy>> {
+        self.meter.energy_history(self.sub_dev_id().to_owned(), outlet, start, end).await
+    }
+
+    pub async fn data_log(&self, outlet: u8, start: SystemTime, end: SystemTime) -> Result<Vec<LoggedReading>> {
+        self.meter.data_log(self.sub_dev_id().to_owned(), outlet, start, end).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use serde_json::json;
+    use super::*;
+    use crate::device::SonoffDevice;
+    use crate::transport::MockTransport;
+
+    // Responses below follow the assumed shapes of `SPMHoursKwh` and
+    // `SPMDataLog`, not ones captured from a device
+
+    fn at(timestamp: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(timestamp)
+    }
+
+    #[tokio::test]
+    async fn test_energy_history() {
+        let mock = Arc::new(MockTransport::with_data(json!({ "start": 1_700_002_800, "kwh": [12, 0, 150] })));
+        let spm = SonoffPowerMeter::from(&SonoffDevice::with_transport(mock.clone()));
+        let hours = spm.energy_history("01000a1b2c".to_owned(), 2, at(1_700_000_000), at(1_700_010_000)).await.unwrap();
+        assert_eq!(mock.requests()[0].0, "/getHoursKwh");
+        assert_eq!(mock.requests()[0].1["data"]["start"], 1_700_000_000);
+        assert_eq!(hours.len(), 3);
+        assert_eq!(hours[2].timestamp, 1_700_010_000);
+        assert!((total_kwh(&hours) - 1.62).abs() < 1e-9);
+
+        assert!(spm.energy_history("01000a1b2c".to_owned(), 2, at(1_700_010_000), at(1_700_000_000)).await.is_err());
+        let too_long = at(1_700_000_000) + HISTORY_MAX + Duration::from_secs(1);
+        assert!(spm.energy_history("01000a1b2c".to_owned(), 2, at(1_700_000_000), too_long).await.is_err());
+        assert_eq!(mock.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_data_log() {
+        let mock = Arc::new(MockTransport::with_data(json!({ "dataLog": [
+            { "time": 1_700_000_000, "current": 150, "voltage": 23012, "actPow": 27600, "reactPow": 20700, "apparentPow": 34500 },
+            { "time": 1_700_000_060, "current": 0, "voltage": 23000, "actPow": 0, "reactPow": 0, "apparentPow": 0 },
+        ]})));
+        let spm = SonoffPowerMeter::from(&SonoffDevice::with_transport(mock.clone()));
+        let log = spm.data_log("01000a1b2c".to_owned(), 1, at(1_700_000_000), at(1_700_000_100)).await.unwrap();
+        assert_eq!(mock.requests()[0].0, "/getDataLog");
+        assert_eq!(mock.requests()[0].1["data"]["outlet"], 1);
+        assert_eq!(log.len(), 2);
+        assert_eq!(log[0].time(), at(1_700_000_000));
+        assert_eq!(log[0].reading.outlet, 1);
+        assert!((log[0].reading.current - 1.5).abs() < 1e-9);
+        assert!((log[0].reading.voltage - 230.12).abs() < 1e-9);
+        assert!((log[0].reading.power_factor.unwrap() - 0.8).abs() < 1e-9);
+        assert_eq!(log[1].reading.power_factor, None);
+        assert!(spm.data_log("01000a1b2c".to_owned(), 4, at(1_700_000_000), at(1_700_000_100)).await.is_err());
+    }
+}
@@ -0,0 +1,121 @@
+use thiserror::Error;
+
+/// Error codes reported by the device in the `error` field of a response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DevErrorCode {
+    /// 400: The request is not valid JSON or has a wrong format
+    BadRequest,
+    /// 401: Authorization failed (e.g. wrong device key in encrypted mode)
+    Unauthorized,
+    /// 403: The operation is not allowed (e.g. OTA is still locked)
+    Forbidden,
+    /// 404: The device does not exist (e.g. wrong device ID)
+    NotFound,
+    /// 422: The request parameters are invalid
+    InvalidParams,
+    /// 500: The device failed internally (e.g. OTA download failed)
+    Internal,
+    /// Any code not listed above
+    Other(u32),
+}
+
+impl From<u32> for DevErrorCode {
+    fn from(value: u32) -> Self {
+        match value {
+            400 => DevErrorCode::BadRequest,
+            401 => DevErrorCode::Unauthorized,
+            403 => DevErrorCode::Forbidden,
+            404 => DevErrorCode::NotFound,
+            422 => DevErrorCode::InvalidParams,
+            500 => DevErrorCode::Internal,
+            other => DevErrorCode::Other(other),
+        }
+    }
+}
+
+impl From<DevErrorCode> for u32 {
+    fn from(value: DevErrorCode) -> Self {
+        match value {
+            DevErrorCode::BadRequest => 400,
+            DevErrorCode::Unauthorized => 401,
+            DevErrorCode::Forbidden => 403,
+            DevErrorCode::NotFound => 404,
+            DevErrorCode::InvalidParams => 422,
+            DevErrorCode::Internal => 500,
+            DevErrorCode::Other(other) => other,
+        }
+    }
+}
+
+impl std::fmt::Display for DevErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let desc = match self {
+            DevErrorCode::BadRequest => "bad request",
+            DevErrorCode::Unauthorized => "unauthorized",
+            DevErrorCode::Forbidden => "forbidden",
+            DevErrorCode::NotFound => "not found",
+            DevErrorCode::InvalidParams => "invalid parameters",
+            DevErrorCode::Internal => "internal device error",
+            DevErrorCode::Other(_) => "unknown error",
+        };
+        write!(f, "{} ({})", desc, u32::from(*self))
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum SonoffError {
+    /// The request could not be sent or the response could not be received
+    #[error("transport error: {0}")]
+    Transport(Box<dyn std::error::Error + Send + Sync>),
+    /// The device answered with a non-success HTTP status
+    #[error("HTTP status {0}")]
+    HttpStatus(reqwest::StatusCode),
+    /// The request or response JSON does not match the expected model
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+    /// The device answered with a non-zero `error` field
+    #[error("device error: {code}")]
+    Device { code: DevErrorCode, seq: u32 },
+    /// A value is out of the range accepted by the device
+    #[error("invalid value: {0}")]
+    InvalidValue(String),
+    /// Local I/O failed (e.g. reading a firmware image)
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    /// Flashing firmware over the air failed
+    #[error("OTA failed: {0}")]
+    Ota(String),
+    /// Receiving SPM real-time measurements failed
+    #[error("monitoring failed: {0}")]
+    Monitor(String),
+    /// Encrypted data could not be decrypted (e.g. wrong device key)
+    #[error("decryption failed")]
+    Decryption,
+    /// mDNS browsing failed
+    #[error("mDNS error: {0}")]
+    Mdns(#[from] mdns_sd::Error),
+    /// The device answered successfully but without the expected `data`
+    #[error("bad response from device")]
+    BadResponse,
+}
+
+impl From<reqwest::Error> for SonoffError {
+    fn from(value: reqwest::Error) -> Self {
+        SonoffError::Transport(Box::new(value))
+    }
+}
+
+pub type Result<T> = std::result::Result<T, SonoffError>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dev_error_code() {
+        assert_eq!(DevErrorCode::from(403), DevErrorCode::Forbidden);
+        assert_eq!(DevErrorCode::from(422), DevErrorCode::InvalidParams);
+        assert_eq!(DevErrorCode::from(418), DevErrorCode::Other(418));
+        assert_eq!(u32::from(DevErrorCode::NotFound), 404);
+    }
+}
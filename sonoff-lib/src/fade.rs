@@ -0,0 +1,181 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use crate::dimmable::SonoffDimmable;
+use crate::error::Result;
+use crate::perceptual::{lightness, luminance};
+use crate::values::Brightness;
+
+/// Time between two brightness updates
+const STEP: Duration = Duration::from_millis(200);
+
+// Models
+// ===================================================================
+
+/// Shape of a fade over time
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+pub enum FadeCurve {
+    #[default]
+    Linear,
+    /// Starts slow, ends fast
+    EaseIn,
+    /// Starts fast, ends slow
+    EaseOut,
+    /// Starts and ends slow
+    EaseInOut,
+    /// Linear in perceived lightness (CIE L*)
+    Perceptual,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FadeOutcome {
+    /// The target brightness was reached
+    Completed,
+    /// Stopped by `cancel_fade` or replaced by a newer fade
+    Cancelled,
+}
+
+// Implementation
+// ===================================================================
+
+/// Fade in progress on a device, shared by every clone of its handle
+#[derive(Default)]
+pub(crate) struct FadeState {
+    /// Incremented by every new fade and every cancellation
+    generation: AtomicU64,
+    /// Held by the running fade, so the requests of two fades never
+    /// interleave
+    running: tokio::sync::Mutex<()>,
+}
+
+impl FadeState {
+    pub(crate) fn cancel(&self) {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+impl FadeCurve {
+    /// Progress of the fade (0 to 1) at time `t` (0 to 1)
+    fn apply(self, t: f32) -> f32 {
+        match self {
+            FadeCurve::Linear | FadeCurve::Perceptual => t,
+            FadeCurve::EaseIn => t * t,
+            FadeCurve::EaseOut => 1.0 - (1.0 - t) * (1.0 - t),
+            FadeCurve::EaseInOut => t * t * (3.0 - 2.0 * t),
+        }
+    }
+
+    /// Brightness at progress `p` (0 to 1) from `start` to `target`
+    fn brightness(self, start: u8, target: u8, p: f32) -> u8 {
+        let (start, target) = (start as f32, target as f32);
+        let br = match self {
+            FadeCurve::Perceptual => {
+                let (l0, l1) = (lightness(start / 100.0), lightness(target / 100.0));
+                luminance(l0 + (l1 - l0) * p) * 100.0
+            },
+            _ => start + (target - start) * p,
+        };
+        br.round().clamp(Brightness::MIN as f32, Brightness::MAX as f32) as u8
+    }
+}
+
+pub(crate) async fn fade<D>(dimmable: &D, target: Brightness, duration: Duration, curve: FadeCurve) -> Result<FadeOutcome>
+where
+    D: SonoffDimmable + ?Sized + Sync
+{
+    let state = &dimmable.get_dev().fade;
+    let generation = state.generation.fetch_add(1, Ordering::SeqCst) + 1;
+    let _running = state.running.lock().await;
+    let cancelled = || state.generation.load(Ordering::SeqCst) != generation;
+    if cancelled() {
+        return Ok(FadeOutcome::Cancelled);
+    }
+    let start = dimmable.get_brightness().await?.get();
+    let steps = (duration.as_millis() / STEP.as_millis()).max(1) as u32;
+    let begin = tokio::time::Instant::now();
+    let mut last = start;
+    for step in 1..=steps {
+        tokio::time::sleep_until(begin + duration * step / steps).await;
+        if cancelled() {
+            return Ok(FadeOutcome::Cancelled);
+        }
+        let br = curve.brightness(start, target.get(), curve.apply(step as f32 / steps as f32));
+        if br != last {
+            dimmable.dim(Brightness::try_from(br)?).await?;
+            last = br;
+        }
+    }
+    Ok(FadeOutcome::Completed)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use serde_json::json;
+    use super::*;
+    use crate::device::SonoffDevice;
+    use crate::dimmer::SonoffDimmer;
+    use crate::transport::MockTransport;
+
+    fn dimmer_mock() -> Arc<MockTransport> {
+        Arc::new(MockTransport::new(|url_path, _| match url_path {
+            "/info" => json!({ "seq": 1, "error": 0, "data": {
+                "deviceid": "1000abcdef",
+                "switch": "on",
+                "startup": "off",
+                "brightness": 10,
+                "mode": 0,
+                "brightmin": 0,
+                "brightmax": 100,
+            }}),
+            _ => json!({ "seq": 2, "error": 0 }),
+        }))
+    }
+
+    fn sent(mock: &MockTransport) -> Vec<u64> {
+        mock.requests().iter()
+            .filter(|(url_path, _)| url_path == "/dimmable")
+            .map(|(_, body)| body["data"]["brightness"].as_u64().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_curves() {
+        assert_eq!(FadeCurve::EaseIn.apply(0.5), 0.25);
+        assert_eq!(FadeCurve::EaseInOut.apply(0.5), 0.5);
+        assert_eq!(FadeCurve::Linear.brightness(10, 50, 0.5), 30);
+        // Half the perceived lightness is well below half the luminance
+        assert_eq!(FadeCurve::Perceptual.brightness(1, 100, 0.5), 22);
+        assert_eq!(FadeCurve::Perceptual.brightness(1, 100, 1.0), 100);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_fade() {
+        let mock = dimmer_mock();
+        let dimmer = SonoffDimmer::from(&SonoffDevice::with_transport(mock.clone()));
+        let target = Brightness::try_from(50).unwrap();
+        let outcome = dimmer.fade_to(target, Duration::from_secs(1), FadeCurve::Linear).await.unwrap();
+        assert_eq!(outcome, FadeOutcome::Completed);
+        assert_eq!(sent(&mock), vec![18, 26, 34, 42, 50]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_fade_replaced() {
+        let mock = dimmer_mock();
+        let dev = SonoffDevice::with_transport(mock.clone());
+        let first = tokio::spawn({
+            let dev = dev.clone();
+            async move {
+                let dimmer = SonoffDimmer::from(&dev);
+                dimmer.fade_to(Brightness::try_from(100).unwrap(), Duration::from_secs(10), FadeCurve::Linear).await
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        let dimmer = SonoffDimmer::from(&dev);
+        let outcome = dimmer.fade_to(Brightness::try_from(1).unwrap(), Duration::ZERO, FadeCurve::Linear).await.unwrap();
+        assert_eq!(outcome, FadeOutcome::Completed);
+        assert_eq!(first.await.unwrap().unwrap(), FadeOutcome::Cancelled);
+        assert_eq!(sent(&mock).last(), Some(&1));
+    }
+}
@@ -1,9 +1,24 @@
+pub mod error;
+pub mod values;
+pub mod client;
+pub mod transport;
+pub mod encryption;
 pub mod device;
 pub mod device_common;
+pub mod discovery;
+pub mod watch;
+pub mod ota;
 pub mod switchable;
+pub mod multi_switchable;
 pub mod dimmable;
+pub mod fade;
+pub mod perceptual;
+pub mod colorable;
 pub mod switch;
 pub mod dimmer;
 pub mod bulb;
+pub mod color;
 pub mod mini_r3;
 pub mod power_meter;
+pub mod monitor;
+pub mod energy;
@@ -1,35 +1,55 @@
-use anyhow::{Result};
+use async_trait::async_trait;
 use serde::{Serialize, Deserialize};
 
 use crate::device::{SonoffDevice, DevRes};
+use crate::error::{Result, SonoffError};
+use crate::multi_switchable::{toggle_locked, SonoffMultiSwitchable};
+use crate::switchable::{SwitchState, StartupState, PulseState};
+use crate::values::PulseWidth;
+
+/// Number of outlets of a MINIR3
+pub const MINI_R3_OUTLETS: u8 = 4;
 
 // JSON models
 // ===================================================================
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct DevDataR3 {
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub switches: Option<Vec<DevDataR3Switch>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub configure: Option<Vec<DevDataR3Startup>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub pulses: Option<Vec<DevDataR3Pulse>>,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DevInfoDataR3 {
+    pub switches: Vec<DevDataR3Switch>,
+    /// Startup state of every outlet
+    pub configure: Vec<DevDataR3Startup>,
+    pub pulses: Vec<DevDataR3Pulse>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct DevDataR3Switch {
     pub outlet: u8,
-    pub switch: String,
+    pub switch: SwitchState,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct DevDataR3Startup {
     pub outlet: u8,
-    pub startup: String,
+    pub startup: StartupState,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct DevDataR3Pulse {
     pub outlet: u8,
-    pub pulse: String,
-    pub switch: String,
+    pub pulse: PulseState,
+    /// State the outlet pulses to, before going back after `width`
+    pub switch: SwitchState,
+    /// Pulse width in milliseconds (see `PulseWidth`)
     pub width: u32,
 }
 
@@ -49,6 +69,11 @@ impl From<&SonoffDevice> for SonoffMiniR3 {
 impl SonoffMiniR3 {
     fn get_dev(&self) -> &SonoffDevice { &self.dev }
 
+    pub async fn get_info(&self) -> Result<DevInfoDataR3> {
+        let info = self.get_dev().get_info().await?;
+        Ok(serde_json::from_value(info.per_device_info)?)
+    }
+
     pub async fn set_switches(&self, switches: Vec<DevDataR3Switch>) -> Result<DevRes> {
         let req_obj = DevDataR3 {
             switches: Some(switches),
@@ -67,7 +92,12 @@ impl SonoffMiniR3 {
         self.get_dev().__request("/startups", req_obj).await
     }
 
+    /// Widths of active pulses are checked (see `PulseWidth`) before sending
+    /// anything.
     pub async fn set_pulses(&self, pulses: Vec<DevDataR3Pulse>) -> Result<DevRes> {
+        for pulse in pulses.iter().filter(|pulse| pulse.pulse == PulseState::On) {
+            PulseWidth::try_from(pulse.width)?;
+        }
         let req_obj = DevDataR3 {
             switches: None,
             configure: None,
@@ -76,3 +106,109 @@ impl SonoffMiniR3 {
         self.get_dev().__request("/pulses", req_obj).await
     }
 }
+
+#[async_trait]
+impl SonoffMultiSwitchable for SonoffMiniR3 {
+    fn outlet_count(&self) -> u8 { MINI_R3_OUTLETS }
+
+    async fn get_outlets(&self) -> Result<Vec<bool>> {
+        let mut outlets = vec![None; MINI_R3_OUTLETS as usize];
+        for switch in self.get_info().await?.switches {
+            if let Some(on) = outlets.get_mut(switch.outlet as usize) {
+                *on = Some(switch.switch.into());
+            }
+        }
+        outlets.into_iter().collect::<Option<_>>().ok_or(SonoffError::BadResponse)
+    }
+
+    async fn set_outlet(&self, outlet: u8, state: SwitchState) -> Result<DevRes> {
+        let outlet = self.check_outlet(outlet)?;
+        self.set_switches(vec![DevDataR3Switch { outlet, switch: state }]).await
+    }
+
+    async fn set_outlet_startup(&self, outlet: u8, startup: StartupState) -> Result<DevRes> {
+        let outlet = self.check_outlet(outlet)?;
+        self.set_startup(vec![DevDataR3Startup { outlet, startup }]).await
+    }
+
+    async fn outlet_toggle(&self, outlet: u8) -> Result<DevRes> {
+        toggle_locked(self, &self.dev, outlet).await
+    }
+
+    /// Keeps the state the outlet pulses to, which is on if the device does
+    /// not report one.
+    async fn set_outlet_pulse(&self, outlet: u8, width: Option<PulseWidth>) -> Result<DevRes> {
+        let outlet = self.check_outlet(outlet)?;
+        let _guard = self.dev.state_lock.lock().await;
+        let switch = self.get_info().await?.pulses.into_iter()
+            .find(|pulse| pulse.outlet == outlet)
+            .map_or(SwitchState::On, |pulse| pulse.switch);
+        let pulse = DevDataR3Pulse {
+            outlet,
+            pulse: if width.is_some() { PulseState::On } else { PulseState::Off },
+            switch,
+            width: width.map_or(0, PulseWidth::get),
+        };
+        self.set_pulses(vec![pulse]).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use serde_json::json;
+    use super::*;
+    use crate::error::SonoffError;
+    use crate::transport::MockTransport;
+
+    #[tokio::test]
+    async fn test_outlets() {
+        let mock = Arc::new(MockTransport::new(|url_path, _| match url_path {
+            "/info" => json!({ "seq": 1, "error": 0, "data": {
+                "deviceid": "1000abcdef",
+                "switches": [
+                    { "outlet": 0, "switch": "off" }, { "outlet": 1, "switch": "on" },
+                    { "outlet": 2, "switch": "off" }, { "outlet": 3, "switch": "off" },
+                ],
+                "configure": [{ "outlet": 0, "startup": "stay" }],
+                "pulses": [{ "outlet": 0, "pulse": "off", "switch": "off", "width": 500 }],
+            }}),
+            _ => json!({ "seq": 2, "error": 0 }),
+        }));
+        let dev = SonoffDevice::with_transport(mock.clone());
+        let r3 = SonoffMiniR3::from(&dev);
+        assert_eq!(r3.get_outlets().await.unwrap(), vec![false, true, false, false]);
+        r3.outlet_toggle(1).await.unwrap();
+        assert_eq!(mock.requests()[2], ("/switches".to_owned(), json!({
+            "deviceId": "",
+            "data": { "switches": [{ "outlet": 1, "switch": "off" }] },
+        })));
+        let err = r3.outlet_on(4).await.unwrap_err();
+        assert!(matches!(err, SonoffError::InvalidValue(_)));
+        assert_eq!(mock.requests().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_outlet_pulse() {
+        let mock = Arc::new(MockTransport::new(|url_path, _| match url_path {
+            "/info" => json!({ "seq": 1, "error": 0, "data": {
+                "deviceid": "1000abcdef",
+                "switches": [],
+                "configure": [],
+                "pulses": [{ "outlet": 0, "pulse": "off", "switch": "off", "width": 500 }],
+            }}),
+            _ => json!({ "seq": 2, "error": 0 }),
+        }));
+        let r3 = SonoffMiniR3::from(&SonoffDevice::with_transport(mock.clone()));
+        r3.set_outlet_pulse(0, Some(PulseWidth::try_from(1500).unwrap())).await.unwrap();
+        assert_eq!(mock.requests()[1].1["data"]["pulses"], json!([
+            { "outlet": 0, "pulse": "on", "switch": "off", "width": 1500 },
+        ]));
+        r3.set_outlet_pulse(2, Some(PulseWidth::try_from(1000).unwrap())).await.unwrap();
+        assert_eq!(mock.requests()[3].1["data"]["pulses"], json!([
+            { "outlet": 2, "pulse": "on", "switch": "on", "width": 1000 },
+        ]));
+        // Outlets left out of `switches` are not reported as off
+        assert!(matches!(r3.get_outlet(0).await.unwrap_err(), SonoffError::BadResponse));
+    }
+}
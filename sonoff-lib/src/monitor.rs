@@ -0,0 +1,275 @@
+//! SPM real-time monitoring: the SPM-MAIN pushes measurements of a
+//! sub-device as UDP datagrams to a host registered with `/monitor`, until
+//! the registration expires.
+//!
+//! Experimental: the published DIY mode API does not describe the
+//! datagrams. `MonitorSample` follows the fields and hundredths scaling of
+//! the other SPM endpoints (see `PowerMeterPVC`) and may change once
+//! checked against a device.
+
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+use futures::Stream;
+use serde::{Deserialize, Serialize};
+use tokio::net::UdpSocket;
+use tokio::time::Instant;
+
+use crate::error::{Result, SonoffError};
+use crate::ota::local_ip_for;
+use crate::power_meter::{PowerReading, SonoffPowerMeter, SonoffSubDevice, SPM_OUTLETS};
+use crate::values::check_range;
+
+/// Registration length used by `monitor`
+pub const MONITOR_TIME: Duration = Duration::from_secs(60);
+/// Longest registration accepted by the device
+pub const MONITOR_TIME_MAX: Duration = Duration::from_secs(3600);
+
+// JSON models
+// ===================================================================
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SPMMonitorReq {
+    /// IP address of the receiving host
+    pub url: String,
+    /// UDP port of the receiving host
+    pub port: u16,
+    pub sub_dev_id: String,
+    /// Seconds to keep sending measurements for
+    pub time: u32,
+}
+
+/// Measurement of one channel, in hundredths of A, V, W, var and VA
+/// (assumed format, see the module documentation)
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MonitorSample {
+    pub sub_dev_id: String,
+    pub outlet: u8,
+    pub current: u32,
+    pub voltage: u32,
+    pub act_pow: u32,
+    pub react_pow: u32,
+    pub apparent_pow: u32,
+}
+
+// Implementation
+// ===================================================================
+
+/// Registration is renewed when 1/`REARM_MARGIN` of it is left, so no
+/// measurement is lost between two registrations
+const REARM_MARGIN: u32 = 4;
+/// First delay before retrying a failed registration, doubled on every
+/// failure
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+const MAX_DATAGRAM: usize = 2048;
+
+impl MonitorSample {
+    pub fn reading(&self) -> PowerReading {
+        PowerReading::new(self.outlet, self.current, self.voltage, self.act_pow, self.react_pow, self.apparent_pow)
+    }
+}
+
+struct Monitor {
+    socket: UdpSocket,
+    meter: SonoffPowerMeter,
+    req: SPMMonitorReq,
+    /// Time between two registrations
+    rearm: Duration,
+    next_arm: Instant,
+    retry_delay: Duration,
+    /// When the device stops sending without another registration
+    expires: Instant,
+}
+
+impl Monitor {
+    async fn next(&mut self) -> Result<PowerReading> {
+        let mut buf = [0; MAX_DATAGRAM];
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep_until(self.next_arm) => {
+                    let armed_at = Instant::now();
+                    match self.meter.get_dev().__request("/monitor", &self.req).await {
+                        Ok(_) => {
+                            self.next_arm = armed_at + self.rearm;
+                            self.retry_delay = RETRY_DELAY;
+                            self.expires = armed_at + Duration::from_secs(self.req.time as u64);
+                        },
+                        // Retried until the registration expires, then
+                        // reported on every further failure
+                        Err(err) => {
+                            self.next_arm = Instant::now() + self.retry_delay;
+                            self.retry_delay = (self.retry_delay * 2).min(self.rearm);
+                            if Instant::now() >= self.expires {
+                                return Err(err);
+                            }
+                        },
+                    }
+                },
+                received = self.socket.recv(&mut buf) => {
+                    // Other datagrams (e.g. of an earlier registration of
+                    // another sub-device) are dropped
+                    let Ok(sample) = serde_json::from_slice::<MonitorSample>(&buf[..received?]) else {
+                        continue;
+                    };
+                    if sample.sub_dev_id == self.req.sub_dev_id && sample.outlet < SPM_OUTLETS {
+                        return Ok(sample.reading());
+                    }
+                },
+            }
+        }
+    }
+}
+
+impl SonoffPowerMeter {
+    /// Streams real-time readings of every channel of a sub-device
+    /// (experimental, see the module documentation). The
+    /// device is registered again before the registration expires, until the
+    /// stream is dropped (the device then stops sending within
+    /// `MONITOR_TIME`). Failed registrations are retried, and only yield an
+    /// error once the registration expired.
+    pub async fn monitor(&self, sub_dev_id: String) -> Result<impl Stream<Item = Result<PowerReading>>> {
+        let address = self.get_dev().address()
+            .ok_or_else(|| SonoffError::Monitor("device has no network address".to_owned()))?;
+        let local_ip = local_ip_for(address, SonoffError::Monitor).await?;
+        self.monitor_from(local_ip, sub_dev_id, MONITOR_TIME).await
+    }
+
+    /// Like `monitor`, receiving on `local_ip` and registering for `time`
+    /// (min=1s, max=1h) at once
+    pub async fn monitor_from(
+        &self,
+        local_ip: IpAddr,
+        sub_dev_id: String,
+        time: Duration,
+    ) -> Result<impl Stream<Item = Result<PowerReading>>> {
+        let time = check_range("monitoring time", time.as_secs(), 1, MONITOR_TIME_MAX.as_secs())? as u32;
+        let socket = UdpSocket::bind(SocketAddr::new(local_ip, 0)).await?;
+        let armed_at = Instant::now();
+        let req = SPMMonitorReq {
+            url: local_ip.to_string(),
+            port: socket.local_addr()?.port(),
+            sub_dev_id,
+            time,
+        };
+        self.get_dev().__request("/monitor", &req).await?;
+        let rearm = Duration::from_secs(time as u64) * (REARM_MARGIN - 1) / REARM_MARGIN;
+        let monitor = Monitor {
+            socket,
+            meter: SonoffPowerMeter::from(self.get_dev()),
+            req,
+            rearm,
+            next_arm: armed_at + rearm,
+            retry_delay: RETRY_DELAY,
+            expires: armed_at + Duration::from_secs(time as u64),
+        };
+        Ok(futures::stream::unfold(monitor, |mut monitor| async move {
+            let reading = monitor.next().await;
+            Some((reading, monitor))
+        }))
+    }
+}
+
+impl SonoffSubDevice {
+    pub async fn monitor(&self) -> Result<impl Stream<Item = Result<PowerReading>>> {
+        self.meter.monitor(self.sub_dev_id().to_owned()).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use futures::StreamExt;
+    use serde_json::json;
+    use super::*;
+    use crate::device::SonoffDevice;
+    use crate::transport::MockTransport;
+
+    /// Datagram in the format assumed by `MonitorSample`, not one captured
+    /// from a device
+    fn assumed_sample(sub_dev_id: &str, outlet: u8, current: u32, act_pow: u32) -> serde_json::Value {
+        json!({
+            "subDevId": sub_dev_id, "outlet": outlet, "current": current, "voltage": 23000,
+            "actPow": act_pow, "reactPow": 0, "apparentPow": act_pow,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_monitor() {
+        let mock = Arc::new(MockTransport::with_data(json!({})));
+        let spm = SonoffPowerMeter::from(&SonoffDevice::with_transport(mock.clone()));
+        let readings = spm.monitor_from([127, 0, 0, 1].into(), "01000a1b2c".to_owned(), Duration::from_secs(10))
+            .await.unwrap();
+        futures::pin_mut!(readings);
+        let (url_path, body) = mock.requests()[0].clone();
+        assert_eq!(url_path, "/monitor");
+        assert_eq!(body["data"]["time"], 10);
+        let port = body["data"]["port"].as_u64().unwrap() as u16;
+
+        let sender = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        for sub_dev_id in ["01000ffff0", "01000a1b2c"] {
+            let sample = assumed_sample(sub_dev_id, 1, 150, 34500);
+            sender.send_to(sample.to_string().as_bytes(), ("127.0.0.1", port)).await.unwrap();
+        }
+        let reading = readings.next().await.unwrap().unwrap();
+        assert_eq!(reading.outlet, 1);
+        assert!((reading.current - 1.5).abs() < 1e-9);
+        assert!((reading.power_factor.unwrap() - 1.0).abs() < 1e-9);
+    }
+
+    /// Device failing the registrations in `failing`, counted from 0
+    fn failing_mock(failing: std::ops::Range<u32>) -> Arc<MockTransport> {
+        let calls = std::sync::atomic::AtomicU32::new(0);
+        Arc::new(MockTransport::new(move |_, _| {
+            match calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst) {
+                call if failing.contains(&call) => json!({ "seq": 1, "error": 500 }),
+                _ => json!({ "seq": 1, "error": 0, "data": {} }),
+            }
+        }))
+    }
+
+    #[tokio::test]
+    async fn test_monitor_rearm_retry() {
+        tokio::time::pause();
+        let mock = failing_mock(1..3);
+        let spm = SonoffPowerMeter::from(&SonoffDevice::with_transport(mock.clone()));
+        let readings = spm.monitor_from([127, 0, 0, 1].into(), "01000a1b2c".to_owned(), Duration::from_secs(10))
+            .await.unwrap();
+        futures::pin_mut!(readings);
+        let port = mock.requests()[0].1["data"]["port"].as_u64().unwrap() as u16;
+
+        // Re-armed after 7.5s, failing twice, then again after 1s and 2s
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_secs(12)).await;
+            let sample = assumed_sample("01000a1b2c", 0, 0, 0);
+            let sender = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            sender.send_to(sample.to_string().as_bytes(), ("127.0.0.1", port)).await.unwrap();
+        });
+        assert!(readings.next().await.unwrap().is_ok());
+        assert_eq!(mock.requests().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_monitor_expired() {
+        tokio::time::pause();
+        let mock = failing_mock(1..u32::MAX);
+        let spm = SonoffPowerMeter::from(&SonoffDevice::with_transport(mock.clone()));
+        let readings = spm.monitor_from([127, 0, 0, 1].into(), "01000a1b2c".to_owned(), Duration::from_secs(4))
+            .await.unwrap();
+        futures::pin_mut!(readings);
+        let err = readings.next().await.unwrap().unwrap_err();
+        assert!(matches!(err, SonoffError::Device { .. }));
+        // Failed after 3s, retried after 1s as the registration expired
+        assert_eq!(mock.requests().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_monitor_time() {
+        let mock = Arc::new(MockTransport::with_data(json!({})));
+        let spm = SonoffPowerMeter::from(&SonoffDevice::with_transport(mock.clone()));
+        let time = MONITOR_TIME_MAX + Duration::from_secs(1);
+        assert!(spm.monitor_from([127, 0, 0, 1].into(), "01000a1b2c".to_owned(), time).await.is_err());
+        assert!(mock.requests().is_empty());
+    }
+}
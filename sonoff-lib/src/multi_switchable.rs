@@ -0,0 +1,63 @@
+use async_trait::async_trait;
+
+use crate::device::{DevRes, SonoffDevice};
+use crate::error::{Result, SonoffError};
+use crate::switchable::{SwitchState, StartupState};
+use crate::values::{check_range, PulseWidth};
+
+/// Relays with one or more outlets, numbered from 0. Single-channel
+/// switches have outlet 0 only.
+#[async_trait]
+pub trait SonoffMultiSwitchable: Send + Sync {
+    /// Number of outlets
+    fn outlet_count(&self) -> u8;
+
+    /// Switch state of every outlet, indexed by outlet number. Fails with
+    /// `SonoffError::BadResponse` if the device leaves an outlet out.
+    async fn get_outlets(&self) -> Result<Vec<bool>>;
+
+    async fn set_outlet(&self, outlet: u8, state: SwitchState) -> Result<DevRes>;
+
+    /// Set the state of `outlet` for when the device restarts
+    async fn set_outlet_startup(&self, outlet: u8, startup: StartupState) -> Result<DevRes>;
+
+    /// Turn `outlet` off `width` after every time it is turned on, or
+    /// deactivate pulse with `None`
+    async fn set_outlet_pulse(&self, outlet: u8, width: Option<PulseWidth>) -> Result<DevRes>;
+
+    /// Checks that `outlet` exists
+    fn check_outlet(&self, outlet: u8) -> Result<u8> {
+        check_range("outlet", outlet, 0, self.outlet_count().saturating_sub(1))
+    }
+
+    async fn get_outlet(&self, outlet: u8) -> Result<bool> {
+        let outlet = self.check_outlet(outlet)?;
+        self.get_outlets().await?.get(outlet as usize).copied().ok_or(SonoffError::BadResponse)
+    }
+
+    async fn outlet_on(&self, outlet: u8) -> Result<DevRes> {
+        self.set_outlet(outlet, SwitchState::On).await
+    }
+
+    async fn outlet_off(&self, outlet: u8) -> Result<DevRes> {
+        self.set_outlet(outlet, SwitchState::Off).await
+    }
+
+    /// Reads the outlet, then writes the opposite state. The relays of this
+    /// crate hold the device state lock in between (see `toggle_locked`).
+    async fn outlet_toggle(&self, outlet: u8) -> Result<DevRes> {
+        let on = self.get_outlet(outlet).await?;
+        self.set_outlet(outlet, (!on).into()).await
+    }
+}
+
+/// `outlet_toggle` holding the state lock of `dev`, so that no other
+/// read-modify-write of the device runs between the read and the write
+pub(crate) async fn toggle_locked<T>(relay: &T, dev: &SonoffDevice, outlet: u8) -> Result<DevRes>
+where
+    T: SonoffMultiSwitchable + ?Sized,
+{
+    let _guard = dev.state_lock.lock().await;
+    let on = relay.get_outlet(outlet).await?;
+    relay.set_outlet(outlet, (!on).into()).await
+}
@@ -0,0 +1,371 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::sync::{watch, Notify};
+use tokio::task::JoinHandle;
+
+use crate::device::SonoffDevice;
+use crate::device_common::UpdateOTAReq;
+use crate::error::{Result, SonoffError};
+
+/// Maximum time for the device to download the whole image
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(300);
+/// Maximum time for the device to come back after downloading the image
+const REBOOT_TIMEOUT: Duration = Duration::from_secs(120);
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// A poll without an answer within this time counts as the device being down
+const POLL_TIMEOUT: Duration = Duration::from_secs(3);
+
+// Models
+// ===================================================================
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OtaProgress {
+    /// The device is downloading the image
+    Downloading { sent: u64, total: u64 },
+    /// The device downloaded the image and is flashing it and rebooting
+    Rebooting,
+    /// The device is back online
+    Done,
+}
+
+// Implementation
+// ===================================================================
+
+/// One-shot HTTP server of a firmware image. Stops accepting connections
+/// once a response carried the whole image, or when dropped.
+pub struct OtaServer {
+    url: String,
+    total: u64,
+    sent: watch::Receiver<u64>,
+    task: JoinHandle<()>,
+}
+
+impl OtaServer {
+    /// Serves `image` on an ephemeral port of `ip`
+    pub async fn bind(ip: IpAddr, image: Vec<u8>) -> Result<OtaServer> {
+        let listener = TcpListener::bind(SocketAddr::new(ip, 0)).await?;
+        let addr = listener.local_addr()?;
+        let url = match addr {
+            SocketAddr::V4(addr) => format!("http://{addr}/ota/image.bin"),
+            SocketAddr::V6(addr) => format!("http://[{}]:{}/ota/image.bin", addr.ip(), addr.port()),
+        };
+        let total = image.len() as u64;
+        let (sent_tx, sent) = watch::channel(0);
+        let image: Arc<[u8]> = image.into();
+        let sent_tx = Arc::new(sent_tx);
+        let downloaded = Arc::new(Notify::new());
+        let task = tokio::spawn(async move {
+            loop {
+                let stream = tokio::select! {
+                    accepted = listener.accept() => match accepted {
+                        Ok((stream, _)) => stream,
+                        Err(_) => break,
+                    },
+                    _ = downloaded.notified() => break,
+                };
+                let image = image.clone();
+                let sent_tx = sent_tx.clone();
+                let downloaded = downloaded.clone();
+                tokio::spawn(async move {
+                    if let Ok(true) = serve_image(stream, &image, &sent_tx).await {
+                        downloaded.notify_one();
+                    }
+                });
+            }
+        });
+        Ok(OtaServer { url, total, sent, task })
+    }
+
+    pub fn url(&self) -> &str { &self.url }
+
+    /// Bytes of the image sent so far. Ranges requested more than once are
+    /// counted every time.
+    pub fn sent(&self) -> u64 { *self.sent.borrow() }
+
+    pub fn total(&self) -> u64 { self.total }
+
+    /// Waits until the amount of bytes sent changes
+    pub async fn changed(&mut self) -> Result<u64> {
+        self.sent.changed().await
+            .map_err(|_| SonoffError::Ota("image server stopped".to_owned()))?;
+        Ok(self.sent())
+    }
+}
+
+impl Drop for OtaServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Parses "bytes=start-end" or "bytes=start-" into an inclusive range
+fn parse_range(value: &str, total: u64) -> Option<(u64, u64)> {
+    let (start, end) = value.trim().strip_prefix("bytes=")?.split_once('-')?;
+    let start: u64 = start.trim().parse().ok()?;
+    let end: u64 = match end.trim() {
+        "" => total.checked_sub(1)?,
+        end => end.parse::<u64>().ok()?.min(total.checked_sub(1)?),
+    };
+    (start <= end).then_some((start, end))
+}
+
+/// Answers one request. Returns whether the whole image was sent.
+async fn serve_image(mut stream: TcpStream, image: &[u8], sent: &watch::Sender<u64>) -> Result<bool> {
+    let mut head = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 || head.len() > 8192 {
+            return Ok(false);
+        }
+        head.extend_from_slice(&buf[..n]);
+    }
+    let head = String::from_utf8_lossy(&head);
+    let mut lines = head.lines();
+    let request_line = lines.next().unwrap_or_default();
+    if !request_line.starts_with("GET /ota/image.bin ") {
+        stream.write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n").await?;
+        return Ok(false);
+    }
+    let total = image.len() as u64;
+    let range = lines
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("range"))
+        .and_then(|(_, value)| parse_range(value, total));
+    let (status, start, end) = match range {
+        Some((start, end)) => ("206 Partial Content", start, end),
+        None => ("200 OK", 0, total.saturating_sub(1)),
+    };
+    let len = if total == 0 { 0 } else { end - start + 1 };
+    let mut res_head = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: application/octet-stream\r\nContent-Length: {len}\r\nConnection: close\r\n",
+    );
+    if range.is_some() {
+        res_head.push_str(&format!("Content-Range: bytes {start}-{end}/{total}\r\n"));
+    }
+    res_head.push_str("\r\n");
+    stream.write_all(res_head.as_bytes()).await?;
+    for chunk in image[start as usize..(start + len) as usize].chunks(4096) {
+        stream.write_all(chunk).await?;
+        sent.send_modify(|sent| *sent += chunk.len() as u64);
+    }
+    stream.flush().await?;
+    Ok(start == 0 && len == total)
+}
+
+/// Local IP address of the interface that routes to `address` (e.g.
+/// "http://192.168.1.2:8081"). Address errors are reported with `error`.
+pub(crate) async fn local_ip_for(address: &str, error: fn(String) -> SonoffError) -> Result<IpAddr> {
+    let url = reqwest::Url::parse(address)
+        .map_err(|e| error(format!("invalid device address: {e}")))?;
+    let host = url.host_str()
+        .ok_or_else(|| error("device address has no host".to_owned()))?
+        .trim_start_matches('[')
+        .trim_end_matches(']');
+    let peer = tokio::net::lookup_host((host, url.port_or_known_default().unwrap_or(80))).await?
+        .next()
+        .ok_or_else(|| error(format!("cannot resolve {host}")))?;
+    let bind_ip: IpAddr = if peer.is_ipv4() {
+        Ipv4Addr::UNSPECIFIED.into()
+    } else {
+        Ipv6Addr::UNSPECIFIED.into()
+    };
+    // Connecting a UDP socket sends nothing but picks the route
+    let socket = UdpSocket::bind((bind_ip, 0)).await?;
+    socket.connect(peer).await?;
+    Ok(socket.local_addr()?.ip())
+}
+
+impl SonoffDevice {
+    /// Flashes the firmware image at `path`. The device must be unlocked
+    /// (see `unlock_ota`).
+    pub async fn flash_ota(&self, path: impl AsRef<Path>) -> Result<()> {
+        self.flash_ota_with_progress(path, |_| {}).await
+    }
+
+    /// Like `flash_ota`, calling `on_progress` as the device downloads the
+    /// image and reboots.
+    pub async fn flash_ota_with_progress(
+        &self,
+        path: impl AsRef<Path>,
+        on_progress: impl FnMut(OtaProgress) + Send,
+    ) -> Result<()> {
+        let address = self.address()
+            .ok_or_else(|| SonoffError::Ota("device has no network address".to_owned()))?;
+        let local_ip = local_ip_for(address, SonoffError::Ota).await?;
+        self.flash_ota_from(local_ip, path, on_progress).await
+    }
+
+    /// Like `flash_ota_with_progress`, serving the image from `local_ip`
+    pub async fn flash_ota_from(
+        &self,
+        local_ip: IpAddr,
+        path: impl AsRef<Path>,
+        mut on_progress: impl FnMut(OtaProgress) + Send,
+    ) -> Result<()> {
+        let image = tokio::fs::read(path).await?;
+        let fw_version = self.get_info().await?.fw_version;
+        let sha256sum = Sha256::digest(&image).iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect();
+        let mut server = OtaServer::bind(local_ip, image).await?;
+        let req_obj = UpdateOTAReq { download_url: server.url().to_owned(), sha256sum };
+        self.__request("/ota_flash", req_obj).await?;
+
+        let total = server.total();
+        on_progress(OtaProgress::Downloading { sent: 0, total });
+        tokio::time::timeout(DOWNLOAD_TIMEOUT, async {
+            let mut sent = server.sent();
+            while sent < total {
+                sent = server.changed().await?;
+                on_progress(OtaProgress::Downloading { sent: sent.min(total), total });
+            }
+            Ok::<_, SonoffError>(())
+        }).await.map_err(|_| SonoffError::Ota("timeout downloading image".to_owned()))??;
+        drop(server);
+
+        on_progress(OtaProgress::Rebooting);
+        tokio::time::timeout(REBOOT_TIMEOUT, async {
+            // Wait for the device to go down and come back. A reboot quicker
+            // than a poll is only noticed by the new firmware version.
+            let mut was_down = false;
+            loop {
+                tokio::time::sleep(POLL_INTERVAL).await;
+                match tokio::time::timeout(POLL_TIMEOUT, self.get_info()).await {
+                    Ok(Ok(info)) if was_down || info.fw_version != fw_version => break,
+                    Ok(Ok(_)) => {},
+                    Ok(Err(_)) | Err(_) => was_down = true,
+                }
+            }
+        }).await.map_err(|_| SonoffError::Ota("timeout waiting for reboot".to_owned()))?;
+        on_progress(OtaProgress::Done);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Mutex;
+    use serde_json::json;
+    use super::*;
+    use crate::transport::MockTransport;
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range("bytes=0-99", 1000), Some((0, 99)));
+        assert_eq!(parse_range("bytes=900-", 1000), Some((900, 999)));
+        assert_eq!(parse_range("bytes=900-5000", 1000), Some((900, 999)));
+        assert_eq!(parse_range("bytes=99-0", 1000), None);
+        assert_eq!(parse_range("items=0-1", 1000), None);
+    }
+
+    #[tokio::test]
+    async fn test_server_range() {
+        let image: Vec<u8> = (0..10_000u32).map(|i| i as u8).collect();
+        let server = OtaServer::bind([127, 0, 0, 1].into(), image.clone()).await.unwrap();
+        let client = reqwest::Client::new();
+        let part = client.get(server.url()).header("Range", "bytes=100-199")
+            .send().await.unwrap();
+        assert_eq!(part.status(), 206);
+        assert_eq!(part.bytes().await.unwrap(), image[100..200]);
+        let full = client.get(server.url()).send().await.unwrap();
+        assert_eq!(full.bytes().await.unwrap(), image);
+        assert_eq!(server.sent(), 10_100);
+    }
+
+    #[tokio::test]
+    async fn test_server_stops_after_download() {
+        let image: Vec<u8> = vec![0xa5; 1000];
+        let server = OtaServer::bind([127, 0, 0, 1].into(), image.clone()).await.unwrap();
+        let client = reqwest::Client::new();
+        let full = client.get(server.url()).send().await.unwrap();
+        assert_eq!(full.bytes().await.unwrap(), image);
+        // The server notices once the response is flushed
+        let mut refused = false;
+        for _ in 0..50 {
+            if client.get(server.url()).send().await.is_err() {
+                refused = true;
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(refused);
+    }
+
+    /// Flashes an image to a mock device whose `/info` answers are given by
+    /// `info` from the number of the call (0 before flashing)
+    async fn flash(info: impl Fn(u32) -> serde_json::Value + Send + Sync + 'static) -> Result<Vec<OtaProgress>> {
+        let image: Vec<u8> = vec![0xa5; 20_000];
+        let path = std::env::temp_dir().join(format!("sonoff-ota-test-{}-{:?}.bin", std::process::id(), std::thread::current().id()));
+        tokio::fs::write(&path, &image).await.unwrap();
+
+        let info_calls = Arc::new(AtomicU32::new(0));
+        let flash_req = Arc::new(Mutex::new(None));
+        let mock = {
+            let flash_req = flash_req.clone();
+            MockTransport::new(move |url_path, body| {
+                match url_path {
+                    "/ota_flash" => {
+                        let url = body["data"]["downloadUrl"].as_str().unwrap().to_owned();
+                        *flash_req.lock().unwrap() = Some(body["data"].clone());
+                        tokio::spawn(async move { reqwest::get(url).await.unwrap().bytes().await.unwrap() });
+                        json!({ "seq": 1, "error": 0 })
+                    },
+                    _ => info(info_calls.fetch_add(1, Ordering::SeqCst)),
+                }
+            })
+        };
+        let dev = SonoffDevice::with_transport(mock);
+        let mut progress = Vec::new();
+        let res = dev.flash_ota_from([127, 0, 0, 1].into(), &path, |p| {
+            // The image is downloaded over real sockets, but the reboot
+            // wait only polls the mock and can skip ahead
+            if p == OtaProgress::Rebooting {
+                tokio::time::pause();
+            }
+            progress.push(p);
+        }).await;
+        tokio::fs::remove_file(&path).await.unwrap();
+        res?;
+
+        let flash_req = flash_req.lock().unwrap().clone().unwrap();
+        let sha256sum: String = Sha256::digest(&image).iter().map(|b| format!("{b:02x}")).collect();
+        assert_eq!(flash_req["sha256sum"], sha256sum);
+        assert!(progress.contains(&OtaProgress::Downloading { sent: 20_000, total: 20_000 }));
+        Ok(progress)
+    }
+
+    fn info(fw_version: &str) -> serde_json::Value {
+        json!({ "seq": 1, "error": 0, "data": { "deviceid": "1000abcdef", "fwVersion": fw_version } })
+    }
+
+    #[tokio::test]
+    async fn test_flash() {
+        // Down for two polls
+        let progress = flash(|call| match call {
+            1 | 2 => json!({ "seq": 1, "error": 500 }),
+            _ => info("3.5.0"),
+        }).await.unwrap();
+        assert_eq!(progress[progress.len() - 2..], [OtaProgress::Rebooting, OtaProgress::Done]);
+    }
+
+    #[tokio::test]
+    async fn test_flash_quick_reboot() {
+        // Back with the new firmware before the first poll
+        let progress = flash(|call| info(if call == 0 { "3.5.0" } else { "3.6.0" })).await.unwrap();
+        assert_eq!(progress.last(), Some(&OtaProgress::Done));
+    }
+
+    #[tokio::test]
+    async fn test_flash_no_reboot() {
+        let err = flash(|_| info("3.5.0")).await.unwrap_err();
+        assert!(matches!(err, SonoffError::Ota(_)));
+    }
+}
@@ -0,0 +1,223 @@
+//! Perceptual brightness: the eye is far more sensitive to changes at low
+//! light levels, so a linear `br` makes steps at the low end look huge and
+//! steps at the high end look tiny. `Perceptual` wraps any dimmable device
+//! and maps perceived brightness to device brightness.
+
+use async_trait::async_trait;
+
+use crate::device::{SonoffDevice, DevRes};
+use crate::dimmable::SonoffDimmable;
+use crate::error::{Result, SonoffError};
+use crate::switchable::SonoffSwitchable;
+use crate::values::{check_range, Brightness};
+
+// Models
+// ===================================================================
+
+/// Relative luminance of a perceived brightness, both from 0 to 1
+#[derive(Debug, Clone, PartialEq)]
+pub enum BrightnessCurve {
+    /// No correction
+    Linear,
+    /// CIE L* lightness
+    CieLightness,
+    /// Power law (2.2 for sRGB)
+    Gamma(f32),
+    /// Relative luminance in percent (min=0, max=100) at evenly spaced
+    /// perceived levels, from the lowest to the highest. Interpolated
+    /// linearly. Like the other curves, 0 maps to the minimum level and 100
+    /// to device brightness 100.
+    Lut(Vec<u8>),
+}
+
+/// Maps perceived brightness (1-100) to device brightness (min level-100).
+/// Brightness 0 is passed through.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BrightnessMapping {
+    curve: BrightnessCurve,
+    min_level: u8,
+}
+
+// Implementation
+// ===================================================================
+
+/// Lowest brightness on the curve. Brightness 0 (off for bulbs) is passed
+/// through as it is.
+const CURVE_MIN: u8 = 1;
+
+/// CIE L* (0-100) of relative luminance `y` (0-1)
+pub(crate) fn lightness(y: f32) -> f32 {
+    if y > 216.0 / 24389.0 {
+        116.0 * y.cbrt() - 16.0
+    } else {
+        y * 24389.0 / 27.0
+    }
+}
+
+/// Relative luminance (0-1) of CIE L* `l` (0-100)
+pub(crate) fn luminance(l: f32) -> f32 {
+    if l > 8.0 {
+        ((l + 16.0) / 116.0).powi(3)
+    } else {
+        l * 27.0 / 24389.0
+    }
+}
+
+impl BrightnessCurve {
+    /// Gamma 2.2
+    pub const GAMMA: BrightnessCurve = BrightnessCurve::Gamma(2.2);
+
+    fn luminance(&self, x: f32) -> f32 {
+        match self {
+            BrightnessCurve::Linear => x,
+            BrightnessCurve::CieLightness => luminance(x * 100.0),
+            BrightnessCurve::Gamma(gamma) => x.powf(*gamma),
+            BrightnessCurve::Lut(levels) => {
+                let pos = x * (levels.len() - 1) as f32;
+                let i = (pos.floor() as usize).min(levels.len() - 2);
+                let (y0, y1) = (levels[i] as f32, levels[i + 1] as f32);
+                (y0 + (y1 - y0) * (pos - i as f32)) / 100.0
+            },
+        }
+    }
+}
+
+impl BrightnessMapping {
+    pub fn new(curve: BrightnessCurve) -> Result<BrightnessMapping> {
+        match &curve {
+            BrightnessCurve::Gamma(gamma) if !gamma.is_finite() || *gamma <= 0.0 => {
+                return Err(SonoffError::InvalidValue(format!("gamma must be positive and finite, got {gamma}")));
+            },
+            BrightnessCurve::Lut(levels) => {
+                if levels.len() < 2 {
+                    return Err(SonoffError::InvalidValue("lookup table needs at least 2 levels".to_owned()));
+                }
+                if levels.windows(2).any(|w| w[0] > w[1]) {
+                    return Err(SonoffError::InvalidValue("lookup table must not decrease".to_owned()));
+                }
+                for level in levels {
+                    check_range("lookup table level", *level, 0, 100)?;
+                }
+            },
+            _ => {},
+        }
+        Ok(BrightnessMapping { curve, min_level: CURVE_MIN })
+    }
+
+    /// Lowest device brightness (min=1, max=99) at which the fixture
+    /// visibly lights up. Perceived brightness 1 maps to it, so the same
+    /// perceived level looks alike on different fixtures.
+    pub fn with_min_level(mut self, min_level: u8) -> Result<BrightnessMapping> {
+        self.min_level = check_range("minimum level", min_level, CURVE_MIN, Brightness::MAX - 1)?;
+        Ok(self)
+    }
+
+    pub fn curve(&self) -> &BrightnessCurve { &self.curve }
+    pub fn min_level(&self) -> u8 { self.min_level }
+
+    pub fn to_device(&self, perceived: Brightness) -> Brightness {
+        if perceived.get() < CURVE_MIN {
+            return perceived;
+        }
+        let x = (perceived.get() - CURVE_MIN) as f32 / (Brightness::MAX - CURVE_MIN) as f32;
+        let y = self.curve.luminance(x).clamp(0.0, 1.0);
+        let min = self.min_level as f32;
+        let br = (min + (Brightness::MAX as f32 - min) * y).round() as u8;
+        Brightness::try_from(br).unwrap_or(perceived)
+    }
+
+    /// Perceived brightness that maps closest to `br`
+    pub fn from_device(&self, br: Brightness) -> Brightness {
+        (Brightness::MIN..=Brightness::MAX)
+            .filter_map(|p| Brightness::try_from(p).ok())
+            .min_by_key(|&p| (self.to_device(p).get() as i16 - br.get() as i16).abs())
+            .unwrap_or(br)
+    }
+}
+
+/// Dimmable device driven with perceived brightness (e.g. a `SonoffDimmer`
+/// or a `SonoffBulb`). Fades also run in perceived brightness.
+pub struct Perceptual<D> {
+    inner: D,
+    mapping: BrightnessMapping,
+}
+
+impl<D> Perceptual<D> {
+    pub fn new(inner: D, mapping: BrightnessMapping) -> Perceptual<D> {
+        Perceptual { inner, mapping }
+    }
+
+    pub fn inner(&self) -> &D { &self.inner }
+    pub fn mapping(&self) -> &BrightnessMapping { &self.mapping }
+}
+
+#[async_trait]
+impl<D: SonoffDimmable + Send + Sync> SonoffSwitchable for Perceptual<D> {
+    fn get_dev(&self) -> &SonoffDevice { self.inner.get_dev() }
+}
+
+#[async_trait]
+impl<D: SonoffDimmable + Send + Sync> SonoffDimmable for Perceptual<D> {
+    async fn dim(&self, br: Brightness) -> Result<DevRes> {
+        self.inner.dim(self.mapping.to_device(br)).await
+    }
+
+    async fn get_brightness(&self) -> Result<Brightness> {
+        Ok(self.mapping.from_device(self.inner.get_brightness().await?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use serde_json::json;
+    use super::*;
+    use crate::dimmer::SonoffDimmer;
+    use crate::transport::MockTransport;
+
+    fn br(value: u8) -> Brightness {
+        Brightness::try_from(value).unwrap()
+    }
+
+    #[test]
+    fn test_curves() {
+        let cie = BrightnessMapping::new(BrightnessCurve::CieLightness).unwrap();
+        assert_eq!(cie.to_device(br(0)), br(0));
+        assert_eq!(cie.to_device(br(1)), br(1));
+        assert_eq!(cie.to_device(br(50)), br(19));
+        assert_eq!(cie.to_device(br(100)), br(100));
+        assert_eq!(cie.from_device(br(19)), br(50));
+
+        let gamma = BrightnessMapping::new(BrightnessCurve::GAMMA).unwrap();
+        assert_eq!(gamma.to_device(br(50)), br(22));
+
+        let lut = BrightnessMapping::new(BrightnessCurve::Lut(vec![0, 10, 100])).unwrap();
+        assert_eq!(lut.to_device(br(50)), br(11));
+        assert!(BrightnessMapping::new(BrightnessCurve::Lut(vec![50, 10])).is_err());
+        assert!(BrightnessMapping::new(BrightnessCurve::Gamma(0.0)).is_err());
+        assert!(BrightnessMapping::new(BrightnessCurve::Gamma(f32::NAN)).is_err());
+        assert!(BrightnessMapping::new(BrightnessCurve::Gamma(f32::INFINITY)).is_err());
+    }
+
+    #[test]
+    fn test_min_level() {
+        let mapping = BrightnessMapping::new(BrightnessCurve::Linear).unwrap()
+            .with_min_level(20).unwrap();
+        assert_eq!(mapping.to_device(br(1)), br(20));
+        assert_eq!(mapping.to_device(br(100)), br(100));
+        let lut = BrightnessMapping::new(BrightnessCurve::Lut(vec![0, 100])).unwrap()
+            .with_min_level(20).unwrap();
+        assert_eq!(lut.to_device(br(1)), br(20));
+        assert!(mapping.with_min_level(100).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_perceptual_dimmer() {
+        let mock = Arc::new(MockTransport::with_data(json!({})));
+        let dev = SonoffDevice::with_transport(mock.clone());
+        let mapping = BrightnessMapping::new(BrightnessCurve::CieLightness).unwrap();
+        let dimmer = Perceptual::new(SonoffDimmer::from(&dev), mapping);
+        dimmer.dim(br(50)).await.unwrap();
+        assert_eq!(mock.requests()[0].1["data"]["brightness"], 19);
+    }
+}
@@ -1,24 +1,37 @@
-use anyhow::{Result, anyhow};
+use std::time::Duration;
+
+use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 
 use crate::device::{SonoffDevice, DevRes};
+use crate::error::{Result, SonoffError};
+use crate::multi_switchable::{toggle_locked, SonoffMultiSwitchable};
+use crate::switchable::{SwitchState, StartupState, PulseState};
+use crate::values::{check_range, PulseWidth};
+
+/// Number of outlets of a sub-device (e.g. SPM-4Relay)
+pub const SPM_OUTLETS: u8 = 4;
+/// Longest blinking accepted by `identify_subdev`
+pub const IDENTIFY_TIME_MAX: Duration = Duration::from_secs(300);
 
 // JSON models
 // ===================================================================
 
+#[allow(non_snake_case)]
 #[derive(Debug, Deserialize)]
-struct PowerMeterStatus {
+pub struct PowerMeterStatus {
     pub switches: Vec<SwitchOutlet>,
     #[serde(flatten)]
     pub pvc_status: PowerMeterPVC,
     #[serde(flatten)]
     pub overload: PowerMeterOverloads,
-    pub faultState: DevPowerMeterFault,
+    pub faultState: FaultState,
 }
 
+#[allow(non_snake_case)]
 #[derive(Debug, Deserialize)]
-struct SubDeviceFailure {
-    pub faultState: DevPowerMeterFault,
+pub struct SubDeviceFailure {
+    pub faultState: FaultState,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -29,22 +42,60 @@ pub struct PowerMeterOverloads {
     pub overload_03: Overload,
 }
 
+/// Raw readings of the four channels, in hundredths of A, V, W, var and VA
+#[allow(non_snake_case)]
 #[derive(Debug, Deserialize, Serialize)]
-struct PowerMeterPVC {
+pub struct PowerMeterPVC {
     pub current_00: u32,
     pub voltage_00: u32,
     pub actPow_00: u32,
     pub reactPow_00: u32,
     pub apparentPow_00: u32,
+    pub current_01: u32,
+    pub voltage_01: u32,
+    pub actPow_01: u32,
+    pub reactPow_01: u32,
+    pub apparentPow_01: u32,
+    pub current_02: u32,
+    pub voltage_02: u32,
+    pub actPow_02: u32,
+    pub reactPow_02: u32,
+    pub apparentPow_02: u32,
+    pub current_03: u32,
+    pub voltage_03: u32,
+    pub actPow_03: u32,
+    pub reactPow_03: u32,
+    pub apparentPow_03: u32,
+}
+
+/// Live readings of one outlet, in real units
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct PowerReading {
+    pub outlet: u8,
+    /// Amperes
+    pub current: f64,
+    /// Volts
+    pub voltage: f64,
+    /// Active power in watts
+    pub active_power: f64,
+    /// Reactive power in var
+    pub reactive_power: f64,
+    /// Apparent power in VA
+    pub apparent_power: f64,
+    /// Active over apparent power (min=0, max=1), or `None` without load
+    pub power_factor: Option<f64>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct SwitchOutlet {
     pub outlet: u32,
-    pub switch: String,
+    pub switch: SwitchState,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+/// Protection limits of one channel, in hundredths of W, V and A. The
+/// outlet turns off when a limit is exceeded for `delayTime`.
+#[allow(non_snake_case)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Overload {
     pub minAP: OverloadValue,
     pub maxAP: OverloadValue,
@@ -54,12 +105,15 @@ pub struct Overload {
     pub delayTime: u32,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct OverloadValue {
+    /// 1 if the limit is enabled, 0 otherwise
     pub en: u32,
     pub val: u32,
 }
 
+/// Limits accepted by the device, in hundredths of W, V and A
+#[allow(non_snake_case)]
 #[derive(Debug, serde::Deserialize)]
 pub struct Threshold {
     pub actPow: Range,
@@ -73,36 +127,100 @@ pub struct Range {
     pub max: u32,
 }
 
-#[derive(Debug, serde::Deserialize)]
+/// Protection limits of one channel, in real units. `None` disables a
+/// limit.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct OverloadConfig {
+    /// Minimum active power in watts
+    pub min_power: Option<f64>,
+    /// Maximum active power in watts
+    pub max_power: Option<f64>,
+    /// Minimum voltage in volts
+    pub min_voltage: Option<f64>,
+    /// Maximum voltage in volts
+    pub max_voltage: Option<f64>,
+    /// Maximum current in amperes
+    pub max_current: Option<f64>,
+    /// How long a limit must be exceeded before the outlet turns off, in
+    /// seconds
+    pub delay: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SPMOverloadReq {
+    pub sub_dev_id: String,
+    pub outlet: u8,
+    #[serde(flatten)]
+    pub overload: Overload,
+}
+
+/// Raw fault state of a sub-device. Trigger lists are only sent while a
+/// fault is active.
+#[allow(non_snake_case)]
+#[derive(Debug, Clone, Default, Deserialize)]
 pub struct FaultState {
+    /// Sub-device link to the SPM-MAIN. 0: normal, otherwise communication
+    /// error.
     pub subDevCom: u32,
     /// Sub-device cse7761 communication error. Array elements are Number type.
     /// The quantity is 4. Elements 0-3 are 1-4 channels respectively.
     /// [0,1] 1: Communication is normal. 0: Communication error.
     pub cse7761Com: Vec<u32>,
-}
-
-#[derive(Debug, Deserialize)]
-struct DevPowerMeterFault {
-    #[serde(flatten)]
-    pub fault_state: FaultState,
+    /// Outlets turned off by the configured protection (see `Overload`)
+    #[serde(default)]
     pub overloadTrig: Vec<OverloadTrigger>,
-    pub cse7761Com: Vec<u32>,
+    /// Per channel, like `cse7761Com`. 1: over temperature.
+    #[serde(default)]
     pub overTemp: Vec<u32>,
+    /// Outlets turned off for exceeding the hardware ratings (see
+    /// `Threshold`)
+    #[serde(default)]
     pub overLimit: Vec<OverloadTrigger>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-struct OverloadTrigger {
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct OverloadTrigger {
     pub outlet: u32,
+    /// Reasons, see `FaultReason::from_code`
     pub rsn: Vec<u32>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
-struct SwitchStatusChange {
+pub struct SwitchStatusChange {
     pub switches: Vec<SwitchOutlet>,
 }
 
+/// Why a channel was turned off
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FaultReason {
+    /// Reported in `FaultState::overTemp`
+    OverTemperature,
+    /// Raw `rsn` code of an `OverloadTrigger`, not decoded by this library
+    Unknown(u32),
+}
+
+/// Active faults of one channel
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ChannelFault {
+    pub outlet: u8,
+    /// Limits exceeded, configured (see `OverloadConfig`) or hardware ones
+    pub reasons: Vec<FaultReason>,
+    /// Link to the metering chip (cse7761) of the channel is down, so its
+    /// readings are not reliable
+    pub meter_link_down: bool,
+}
+
+/// Decoded fault state of a sub-device
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SpmFaultReport {
+    pub sub_dev_id: String,
+    /// The sub-device does not answer the SPM-MAIN
+    pub link_down: bool,
+    /// Channels with at least one fault, by outlet number
+    pub channels: Vec<ChannelFault>,
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -114,7 +232,35 @@ pub struct SPMSwitchesReq {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct DevDataSPMSwitch {
     pub outlet: u8,
-    pub switch: String,
+    pub switch: SwitchState,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SPMStartupsReq {
+    pub sub_dev_id: String,
+    pub configure: Vec<DevDataSPMStartup>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DevDataSPMStartup {
+    pub outlet: u8,
+    pub startup: StartupState,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SPMPulsesReq {
+    pub sub_dev_id: String,
+    pub pulses: Vec<DevDataSPMPulse>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DevDataSPMPulse {
+    pub outlet: u8,
+    pub pulse: PulseState,
+    /// Pulse width in milliseconds (see `PulseWidth`)
+    pub width: u32,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -130,8 +276,24 @@ pub struct SPMSubdevList {
 #[serde(rename_all = "camelCase")]
 pub struct DevDataSPMSubdev {
     pub sub_dev_id: String,
+    /// Raw model code of the sub-device. The codes are not documented, so
+    /// they are not decoded.
     #[serde(rename = "type")]
-    pub _type: u32,
+    pub kind: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SPMSubdevReq {
+    pub sub_dev_id: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SPMIdentifyReq {
+    pub sub_dev_id: String,
+    /// Seconds to blink the sub-device LED for
+    pub time: u32,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -144,7 +306,7 @@ pub struct SPMStatusReq {
 #[serde(rename_all = "camelCase")]
 pub struct SPMStatus {
     pub deviceid: String,
-    pub sled_online: String,
+    pub sled_online: SwitchState,
     pub ssid: String,
     pub bssid: String,
     pub fw_version: String,
@@ -154,10 +316,16 @@ pub struct SPMStatus {
 }
 
 
+#[allow(non_snake_case)]
 #[derive(Debug, serde::Deserialize)]
 pub struct SPMSubdevStatus {
     pub fwVersion: String,
     pub switches: Vec<SwitchOutlet>,
+    /// Startup state of every outlet
+    #[serde(default)]
+    pub configure: Vec<DevDataSPMStartup>,
+    #[serde(default)]
+    pub pulses: Vec<DevDataSPMPulse>,
     #[serde(flatten)]
     pub overload: PowerMeterOverloads,
     pub faultState: FaultState,
@@ -168,6 +336,183 @@ pub struct SPMSubdevStatus {
 // Implementation
 // ===================================================================
 
+/// Readings are sent in hundredths of the unit
+const PVC_SCALE: f64 = 0.01;
+
+impl PowerMeterPVC {
+    /// Readings of `outlet`, or `None` if there is no such outlet
+    pub fn reading(&self, outlet: u8) -> Option<PowerReading> {
+        let (current, voltage, act_pow, react_pow, apparent_pow) = match outlet {
+            0 => (self.current_00, self.voltage_00, self.actPow_00, self.reactPow_00, self.apparentPow_00),
+            1 => (self.current_01, self.voltage_01, self.actPow_01, self.reactPow_01, self.apparentPow_01),
+            2 => (self.current_02, self.voltage_02, self.actPow_02, self.reactPow_02, self.apparentPow_02),
+            3 => (self.current_03, self.voltage_03, self.actPow_03, self.reactPow_03, self.apparentPow_03),
+            _ => return None,
+        };
+        Some(PowerReading::new(outlet, current, voltage, act_pow, react_pow, apparent_pow))
+    }
+}
+
+impl PowerMeterOverloads {
+    pub fn get(&self, outlet: u8) -> Option<&Overload> {
+        match outlet {
+            0 => Some(&self.overload_00),
+            1 => Some(&self.overload_01),
+            2 => Some(&self.overload_02),
+            3 => Some(&self.overload_03),
+            _ => None,
+        }
+    }
+}
+
+impl From<&Overload> for OverloadConfig {
+    fn from(value: &Overload) -> Self {
+        let limit = |value: &OverloadValue| (value.en != 0).then_some(value.val as f64 * PVC_SCALE);
+        OverloadConfig {
+            min_power: limit(&value.minAP),
+            max_power: limit(&value.maxAP),
+            min_voltage: limit(&value.minV),
+            max_voltage: limit(&value.maxV),
+            max_current: limit(&value.maxC),
+            delay: value.delayTime,
+        }
+    }
+}
+
+impl OverloadConfig {
+    /// Raw limits, checked against the limits accepted by the device
+    pub fn to_overload(&self, threshold: &Threshold) -> Result<Overload> {
+        let limit = |name: &str, value: Option<f64>, range: &Range| -> Result<OverloadValue> {
+            let Some(value) = value else {
+                return Ok(OverloadValue { en: 0, val: 0 });
+            };
+            let raw = (value / PVC_SCALE).round();
+            if !(range.min as f64..=range.max as f64).contains(&raw) {
+                return Err(SonoffError::InvalidValue(format!(
+                    "{name} must be between {} and {}, got {value}",
+                    range.min as f64 * PVC_SCALE, range.max as f64 * PVC_SCALE,
+                )));
+            }
+            Ok(OverloadValue { en: 1, val: raw as u32 })
+        };
+        let overload = Overload {
+            minAP: limit("minimum power", self.min_power, &threshold.actPow)?,
+            maxAP: limit("maximum power", self.max_power, &threshold.actPow)?,
+            minV: limit("minimum voltage", self.min_voltage, &threshold.voltage)?,
+            maxV: limit("maximum voltage", self.max_voltage, &threshold.voltage)?,
+            maxC: limit("maximum current", self.max_current, &threshold.current)?,
+            delayTime: self.delay,
+        };
+        for (name, min, max) in [
+            ("power", &overload.minAP, &overload.maxAP),
+            ("voltage", &overload.minV, &overload.maxV),
+        ] {
+            if min.en != 0 && max.en != 0 && min.val >= max.val {
+                return Err(SonoffError::InvalidValue(format!("minimum {name} must be lower than maximum {name}")));
+            }
+        }
+        Ok(overload)
+    }
+}
+
+impl FaultReason {
+    /// Decodes a `rsn` code of `OverloadTrigger`.
+    ///
+    /// The DIY mode API does not document these codes, so every code is
+    /// kept as `Unknown` until its meaning is known.
+    pub fn from_code(code: u32) -> FaultReason {
+        FaultReason::Unknown(code)
+    }
+}
+
+impl std::fmt::Display for FaultReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FaultReason::OverTemperature => write!(f, "over-temperature"),
+            FaultReason::Unknown(code) => write!(f, "rsn {code}"),
+        }
+    }
+}
+
+impl SpmFaultReport {
+    pub fn new(sub_dev_id: String, state: &FaultState) -> SpmFaultReport {
+        let mut channels: Vec<ChannelFault> = (0..SPM_OUTLETS)
+            .map(|outlet| ChannelFault { outlet, reasons: Vec::new(), meter_link_down: false })
+            .collect();
+        let triggers = state.overloadTrig.iter().chain(&state.overLimit);
+        for trigger in triggers {
+            if let Some(channel) = channels.get_mut(trigger.outlet as usize) {
+                for reason in trigger.rsn.iter().map(|&code| FaultReason::from_code(code)) {
+                    if !channel.reasons.contains(&reason) {
+                        channel.reasons.push(reason);
+                    }
+                }
+            }
+        }
+        for (channel, &over_temp) in channels.iter_mut().zip(&state.overTemp) {
+            if over_temp != 0 {
+                channel.reasons.push(FaultReason::OverTemperature);
+            }
+        }
+        for (channel, &com) in channels.iter_mut().zip(&state.cse7761Com) {
+            channel.meter_link_down = com == 0;
+        }
+        channels.retain(ChannelFault::is_active);
+        SpmFaultReport { sub_dev_id, link_down: state.subDevCom != 0, channels }
+    }
+
+    /// True if no fault is active
+    pub fn is_ok(&self) -> bool {
+        !self.link_down && self.channels.is_empty()
+    }
+}
+
+impl ChannelFault {
+    pub fn is_active(&self) -> bool {
+        !self.reasons.is_empty() || self.meter_link_down
+    }
+}
+
+impl std::fmt::Display for SpmFaultReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_ok() {
+            return write!(f, "{}: no faults", self.sub_dev_id);
+        }
+        write!(f, "{}:", self.sub_dev_id)?;
+        if self.link_down {
+            write!(f, "\n  sub-device link down")?;
+        }
+        for channel in &self.channels {
+            for reason in &channel.reasons {
+                write!(f, "\n  outlet{} tripped: {reason}", channel.outlet)?;
+            }
+            if channel.meter_link_down {
+                write!(f, "\n  outlet{} metering chip link down", channel.outlet)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl PowerReading {
+    /// Reading from raw values, in hundredths of the unit
+    pub fn new(outlet: u8, current: u32, voltage: u32, act_pow: u32, react_pow: u32, apparent_pow: u32) -> PowerReading {
+        let power_factor = match apparent_pow {
+            0 => None,
+            _ => Some((act_pow as f64 / apparent_pow as f64).min(1.0)),
+        };
+        PowerReading {
+            outlet,
+            current: current as f64 * PVC_SCALE,
+            voltage: voltage as f64 * PVC_SCALE,
+            active_power: act_pow as f64 * PVC_SCALE,
+            reactive_power: react_pow as f64 * PVC_SCALE,
+            apparent_power: apparent_pow as f64 * PVC_SCALE,
+            power_factor,
+        }
+    }
+}
+
 pub struct SonoffPowerMeter {
     dev: SonoffDevice,
 }
@@ -179,18 +524,58 @@ impl From<&SonoffDevice> for SonoffPowerMeter {
 }
 
 impl SonoffPowerMeter {
-    fn get_dev(&self) -> &SonoffDevice { &self.dev }
+    pub(crate) fn get_dev(&self) -> &SonoffDevice { &self.dev }
 
     pub async fn set_switches(&self, sub_dev_id: String, switches: Vec<DevDataSPMSwitch>) -> Result<DevRes> {
         let req_obj = SPMSwitchesReq { sub_dev_id, switches };
         self.get_dev().__request("/switches", req_obj).await
     }
 
+    pub async fn set_startups(&self, sub_dev_id: String, configure: Vec<DevDataSPMStartup>) -> Result<DevRes> {
+        let req_obj = SPMStartupsReq { sub_dev_id, configure };
+        self.get_dev().__request("/startups", req_obj).await
+    }
+
+    /// Widths of active pulses are checked (see `PulseWidth`) before sending
+    /// anything.
+    pub async fn set_pulses(&self, sub_dev_id: String, pulses: Vec<DevDataSPMPulse>) -> Result<DevRes> {
+        for pulse in pulses.iter().filter(|pulse| pulse.pulse == PulseState::On) {
+            PulseWidth::try_from(pulse.width)?;
+        }
+        let req_obj = SPMPulsesReq { sub_dev_id, pulses };
+        self.get_dev().__request("/pulses", req_obj).await
+    }
+
     pub async fn get_subdevs(&self) -> Result<SPMSubdevList> {
         let req_obj = SPMSubdevListReq { };
         self.get_dev().request("/subDevList", req_obj).await
     }
 
+    // Sub-device management is experimental: `/subDevAdd`, `/subDevDelete`
+    // and `/subDevIdentify` are not in the published DIY mode API, and
+    // firmware without them answers with HTTP 404.
+
+    /// Pairs the sub-device with the ID printed on its label
+    pub async fn add_subdev(&self, sub_dev_id: String) -> Result<SonoffSubDevice> {
+        let req_obj = SPMSubdevReq { sub_dev_id: sub_dev_id.to_owned() };
+        self.get_dev().__request("/subDevAdd", req_obj).await?;
+        Ok(self.sub_device(sub_dev_id))
+    }
+
+    /// Unpairs a sub-device
+    pub async fn remove_subdev(&self, sub_dev_id: String) -> Result<DevRes> {
+        let req_obj = SPMSubdevReq { sub_dev_id };
+        self.get_dev().__request("/subDevDelete", req_obj).await
+    }
+
+    /// Blinks the LED of a sub-device for `duration` (min=1s, max=5min), to
+    /// find it in the cabinet
+    pub async fn identify_subdev(&self, sub_dev_id: String, duration: Duration) -> Result<DevRes> {
+        let time = check_range("identify time", duration.as_secs(), 1, IDENTIFY_TIME_MAX.as_secs())? as u32;
+        let req_obj = SPMIdentifyReq { sub_dev_id, time };
+        self.get_dev().__request("/subDevIdentify", req_obj).await
+    }
+
     pub async fn status(&self) -> Result<SPMStatus> {
         let req_obj = SPMStatusReq { sub_dev_id: None };
         self.get_dev().request("/getState", req_obj).await
@@ -200,4 +585,358 @@ impl SonoffPowerMeter {
         let req_obj = SPMStatusReq { sub_dev_id: Some(sub_dev_id) };
         self.get_dev().request("/getState", req_obj).await
     }
+
+    /// Live readings of `outlet` (min=0, max=3) of a sub-device
+    pub async fn read_power(&self, sub_dev_id: String, outlet: u8) -> Result<PowerReading> {
+        check_range("outlet", outlet, 0, SPM_OUTLETS - 1)?;
+        let mut readings = self.read_power_all(sub_dev_id).await?;
+        Ok(readings.swap_remove(outlet as usize))
+    }
+
+    /// Live readings of every outlet of a sub-device, with one request
+    pub async fn read_power_all(&self, sub_dev_id: String) -> Result<Vec<PowerReading>> {
+        let req_obj = SPMStatusReq { sub_dev_id: Some(sub_dev_id) };
+        let pvc: PowerMeterPVC = self.get_dev().request("/getState", req_obj).await?;
+        Ok((0..SPM_OUTLETS).filter_map(|outlet| pvc.reading(outlet)).collect())
+    }
+
+    /// Protection limits of `outlet` (min=0, max=3) of a sub-device
+    pub async fn get_overload(&self, sub_dev_id: String, outlet: u8) -> Result<OverloadConfig> {
+        let outlet = check_range("outlet", outlet, 0, SPM_OUTLETS - 1)?;
+        let status = self.subdev_status(sub_dev_id).await?;
+        let overload = status.overload.get(outlet).ok_or(SonoffError::BadResponse)?;
+        Ok(overload.into())
+    }
+
+    /// Sets the protection limits of `outlet` (min=0, max=3) of a
+    /// sub-device. Limits are checked against the `Threshold` reported by
+    /// the device before sending them. Experimental: `/overload` is not in
+    /// the published DIY mode API.
+    pub async fn set_overload(&self, sub_dev_id: String, outlet: u8, config: OverloadConfig) -> Result<DevRes> {
+        let (res, _) = self.modify_overload(sub_dev_id, outlet, |current| *current = config).await?;
+        Ok(res)
+    }
+
+    /// Changes the protection limits of `outlet` (min=0, max=3) of a
+    /// sub-device with `f`, starting from the current ones, and returns the
+    /// limits sent. The status is fetched only once.
+    pub async fn update_overload<F>(&self, sub_dev_id: String, outlet: u8, f: F) -> Result<OverloadConfig>
+    where
+        F: FnOnce(&mut OverloadConfig) + Send,
+    {
+        let (_, config) = self.modify_overload(sub_dev_id, outlet, f).await?;
+        Ok(config)
+    }
+
+    async fn modify_overload<F>(&self, sub_dev_id: String, outlet: u8, f: F) -> Result<(DevRes, OverloadConfig)>
+    where
+        F: FnOnce(&mut OverloadConfig) + Send,
+    {
+        let outlet = check_range("outlet", outlet, 0, SPM_OUTLETS - 1)?;
+        let _guard = self.get_dev().state_lock.lock().await;
+        let status = self.subdev_status(sub_dev_id.to_owned()).await?;
+        let mut config = OverloadConfig::from(status.overload.get(outlet).ok_or(SonoffError::BadResponse)?);
+        f(&mut config);
+        let overload = config.to_overload(&status.threshold)?;
+        let req_obj = SPMOverloadReq { sub_dev_id, outlet, overload };
+        let res = self.get_dev().__request("/overload", req_obj).await?;
+        Ok((res, config))
+    }
+
+    /// Active faults of a sub-device
+    pub async fn faults(&self, sub_dev_id: String) -> Result<SpmFaultReport> {
+        let status = self.subdev_status(sub_dev_id.to_owned()).await?;
+        Ok(SpmFaultReport::new(sub_dev_id, &status.faultState))
+    }
+
+    /// Outlets of the sub-device `sub_dev_id` (see `get_subdevs`)
+    pub fn sub_device(&self, sub_dev_id: impl Into<String>) -> SonoffSubDevice {
+        SonoffSubDevice { meter: SonoffPowerMeter::from(&self.dev), sub_dev_id: sub_dev_id.into() }
+    }
+}
+
+/// Sub-device of a SPM-MAIN, with `SPM_OUTLETS` outlets
+pub struct SonoffSubDevice {
+    pub(crate) meter: SonoffPowerMeter,
+    sub_dev_id: String,
+}
+
+impl SonoffSubDevice {
+    pub fn sub_dev_id(&self) -> &str { &self.sub_dev_id }
+
+    pub async fn status(&self) -> Result<SPMSubdevStatus> {
+        self.meter.subdev_status(self.sub_dev_id.to_owned()).await
+    }
+
+    pub async fn read_power(&self, outlet: u8) -> Result<PowerReading> {
+        self.meter.read_power(self.sub_dev_id.to_owned(), outlet).await
+    }
+
+    pub async fn faults(&self) -> Result<SpmFaultReport> {
+        self.meter.faults(self.sub_dev_id.to_owned()).await
+    }
+
+    /// State of `outlet` (min=0, max=3) when the sub-device restarts
+    pub async fn get_outlet_startup(&self, outlet: u8) -> Result<StartupState> {
+        let outlet = self.check_outlet(outlet)?;
+        self.status().await?.configure.into_iter()
+            .find(|startup| startup.outlet == outlet)
+            .map(|startup| startup.startup)
+            .ok_or(SonoffError::BadResponse)
+    }
+
+    /// Pulse width of `outlet` (min=0, max=3) in milliseconds, as reported
+    /// by the device, or `None` if pulse is off
+    pub async fn get_outlet_pulse(&self, outlet: u8) -> Result<Option<u32>> {
+        let outlet = self.check_outlet(outlet)?;
+        let pulse = self.status().await?.pulses.into_iter()
+            .find(|pulse| pulse.outlet == outlet)
+            .ok_or(SonoffError::BadResponse)?;
+        match pulse.pulse {
+            PulseState::On => Ok(Some(pulse.width)),
+            PulseState::Off => Ok(None),
+        }
+    }
+
+    pub async fn identify(&self, duration: Duration) -> Result<DevRes> {
+        self.meter.identify_subdev(self.sub_dev_id.to_owned(), duration).await
+    }
+
+    pub async fn remove(self) -> Result<DevRes> {
+        self.meter.remove_subdev(self.sub_dev_id).await
+    }
+
+    pub async fn get_overload(&self, outlet: u8) -> Result<OverloadConfig> {
+        self.meter.get_overload(self.sub_dev_id.to_owned(), outlet).await
+    }
+
+    pub async fn set_overload(&self, outlet: u8, config: OverloadConfig) -> Result<DevRes> {
+        self.meter.set_overload(self.sub_dev_id.to_owned(), outlet, config).await
+    }
+
+    pub async fn update_overload<F>(&self, outlet: u8, f: F) -> Result<OverloadConfig>
+    where
+        F: FnOnce(&mut OverloadConfig) + Send,
+    {
+        self.meter.update_overload(self.sub_dev_id.to_owned(), outlet, f).await
+    }
+}
+
+#[async_trait]
+impl SonoffMultiSwitchable for SonoffSubDevice {
+    fn outlet_count(&self) -> u8 { SPM_OUTLETS }
+
+    async fn get_outlets(&self) -> Result<Vec<bool>> {
+        let mut outlets = vec![None; SPM_OUTLETS as usize];
+        for switch in self.status().await?.switches {
+            if let Some(on) = outlets.get_mut(switch.outlet as usize) {
+                *on = Some(switch.switch.into());
+            }
+        }
+        outlets.into_iter().collect::<Option<_>>().ok_or(SonoffError::BadResponse)
+    }
+
+    async fn set_outlet(&self, outlet: u8, state: SwitchState) -> Result<DevRes> {
+        let outlet = self.check_outlet(outlet)?;
+        let switches = vec![DevDataSPMSwitch { outlet, switch: state }];
+        self.meter.set_switches(self.sub_dev_id.to_owned(), switches).await
+    }
+
+    async fn set_outlet_startup(&self, outlet: u8, startup: StartupState) -> Result<DevRes> {
+        let outlet = self.check_outlet(outlet)?;
+        let configure = vec![DevDataSPMStartup { outlet, startup }];
+        self.meter.set_startups(self.sub_dev_id.to_owned(), configure).await
+    }
+
+    async fn set_outlet_pulse(&self, outlet: u8, width: Option<PulseWidth>) -> Result<DevRes> {
+        let outlet = self.check_outlet(outlet)?;
+        let pulse = DevDataSPMPulse {
+            outlet,
+            pulse: if width.is_some() { PulseState::On } else { PulseState::Off },
+            width: width.map_or(0, PulseWidth::get),
+        };
+        self.meter.set_pulses(self.sub_dev_id.to_owned(), vec![pulse]).await
+    }
+
+    async fn outlet_toggle(&self, outlet: u8) -> Result<DevRes> {
+        toggle_locked(self, self.meter.get_dev(), outlet).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use serde_json::json;
+    use super::*;
+    use crate::transport::MockTransport;
+
+    #[tokio::test]
+    async fn test_read_power() {
+        let mut data = json!({});
+        for i in 0..SPM_OUTLETS {
+            let on = i == 2;
+            data[format!("current_{i:02}")] = json!(if on { 150 } else { 0 });
+            data[format!("voltage_{i:02}")] = json!(23012);
+            data[format!("actPow_{i:02}")] = json!(if on { 27600 } else { 0 });
+            data[format!("reactPow_{i:02}")] = json!(if on { 20700 } else { 0 });
+            data[format!("apparentPow_{i:02}")] = json!(if on { 34500 } else { 0 });
+        }
+        let mock = Arc::new(MockTransport::with_data(data));
+        let spm = SonoffPowerMeter::from(&SonoffDevice::with_transport(mock.clone()));
+        let reading = spm.read_power("01000a1b2c".to_owned(), 2).await.unwrap();
+        assert_eq!(reading.outlet, 2);
+        assert!((reading.current - 1.5).abs() < 1e-9);
+        assert!((reading.voltage - 230.12).abs() < 1e-9);
+        assert!((reading.active_power - 276.0).abs() < 1e-9);
+        assert!((reading.power_factor.unwrap() - 0.8).abs() < 1e-9);
+        assert_eq!(mock.requests()[0].1["data"], json!({ "subDevId": "01000a1b2c" }));
+
+        let reading = spm.read_power("01000a1b2c".to_owned(), 0).await.unwrap();
+        assert_eq!(reading.power_factor, None);
+        assert!(spm.read_power("01000a1b2c".to_owned(), 4).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_subdevs() {
+        let mock = Arc::new(MockTransport::with_data(json!({ "subDevList": [
+            { "subDevId": "01000a1b2c", "type": 7 },
+            { "subDevId": "01000d3e4f", "type": 12 },
+        ]})));
+        let spm = SonoffPowerMeter::from(&SonoffDevice::with_transport(mock.clone()));
+        let list = spm.get_subdevs().await.unwrap().sub_dev_list;
+        assert_eq!((list[0].sub_dev_id.as_str(), list[0].kind), ("01000a1b2c", 7));
+        assert_eq!(list[1].kind, 12);
+
+        let pulse = DevDataSPMPulse { outlet: 0, pulse: PulseState::On, width: 700 };
+        assert!(spm.set_pulses("01000a1b2c".to_owned(), vec![pulse]).await.is_err());
+        assert!(spm.identify_subdev("01000a1b2c".to_owned(), Duration::ZERO).await.is_err());
+        assert_eq!(mock.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_subdev_management() {
+        // Request shapes are assumed, these endpoints are not documented
+        let mock = Arc::new(MockTransport::with_data(json!({})));
+        let spm = SonoffPowerMeter::from(&SonoffDevice::with_transport(mock.clone()));
+        let sub_device = spm.add_subdev("01000d3e4f".to_owned()).await.unwrap();
+        sub_device.identify(Duration::from_secs(10)).await.unwrap();
+        sub_device.remove().await.unwrap();
+        let requests = mock.requests();
+        assert_eq!(requests[0].0, "/subDevAdd");
+        assert_eq!(requests[0].1["data"], json!({ "subDevId": "01000d3e4f" }));
+        assert_eq!(requests[1].0, "/subDevIdentify");
+        assert_eq!(requests[1].1["data"], json!({ "subDevId": "01000d3e4f", "time": 10 }));
+        assert_eq!(requests[2].0, "/subDevDelete");
+    }
+
+    /// Sub-device status with every protection limit disabled
+    fn subdev_state() -> serde_json::Value {
+        let mut data = json!({
+            "fwVersion": "1.0.0",
+            "switches": [{ "outlet": 0, "switch": "off" }],
+            "pulses": [],
+            "faultState": { "subDevCom": 0, "cse7761Com": [1, 1, 1, 1] },
+            "threshold": {
+                "actPow": { "min": 10, "max": 440000 },
+                "voltage": { "min": 10, "max": 24000 },
+                "current": { "min": 10, "max": 2000 },
+            },
+        });
+        let disabled = json!({ "en": 0, "val": 0 });
+        for i in 0..SPM_OUTLETS {
+            data[format!("overload_{i:02}")] = json!({
+                "minAP": disabled, "maxAP": disabled, "minV": disabled, "maxV": disabled, "maxC": disabled,
+                "delayTime": 0,
+            });
+        }
+        data
+    }
+
+    #[tokio::test]
+    async fn test_outlet_pulse() {
+        let mut data = subdev_state();
+        data["pulses"] = json!([{ "outlet": 0, "pulse": "on", "width": 700 }, { "outlet": 1, "pulse": "off", "width": 500 }]);
+        let mock = Arc::new(MockTransport::with_data(data));
+        let sub_device = SonoffPowerMeter::from(&SonoffDevice::with_transport(mock)).sub_device("01000a1b2c");
+        // Reported as is, even if it could not be written back
+        assert_eq!(sub_device.get_outlet_pulse(0).await.unwrap(), Some(700));
+        assert_eq!(sub_device.get_outlet_pulse(1).await.unwrap(), None);
+        assert!(sub_device.get_outlet_pulse(2).await.is_err());
+    }
+
+    #[test]
+    fn test_fault_report() {
+        let state: FaultState = serde_json::from_value(json!({
+            "subDevCom": 0,
+            "cse7761Com": [1, 0, 1, 1],
+            "overloadTrig": [{ "outlet": 2, "rsn": [4, 1] }],
+            "overTemp": [0, 0, 1, 0],
+            "overLimit": [{ "outlet": 3, "rsn": [9] }],
+        })).unwrap();
+        let report = SpmFaultReport::new("01000a1b2c".to_owned(), &state);
+        assert!(!report.is_ok());
+        assert_eq!(report.channels, vec![
+            ChannelFault { outlet: 1, reasons: vec![], meter_link_down: true },
+            ChannelFault {
+                outlet: 2,
+                reasons: vec![FaultReason::Unknown(4), FaultReason::Unknown(1), FaultReason::OverTemperature],
+                meter_link_down: false,
+            },
+            ChannelFault { outlet: 3, reasons: vec![FaultReason::Unknown(9)], meter_link_down: false },
+        ]);
+        assert!(report.to_string().contains("outlet2 tripped: rsn 4\n  outlet2 tripped: rsn 1"));
+
+        let healthy: FaultState = serde_json::from_value(json!({ "subDevCom": 0, "cse7761Com": [1, 1, 1, 1] })).unwrap();
+        assert!(SpmFaultReport::new("01000a1b2c".to_owned(), &healthy).is_ok());
+    }
+
+    #[test]
+    fn test_overload_config() {
+        let threshold: Threshold = serde_json::from_value(json!({
+            "actPow": { "min": 10, "max": 440000 },
+            "voltage": { "min": 10, "max": 24000 },
+            "current": { "min": 10, "max": 2000 },
+        })).unwrap();
+        let config = OverloadConfig { max_power: Some(1500.0), max_current: Some(8.5), delay: 5, ..Default::default() };
+        let overload = config.to_overload(&threshold).unwrap();
+        assert_eq!((overload.maxAP.en, overload.maxAP.val), (1, 150000));
+        assert_eq!((overload.maxC.en, overload.maxC.val), (1, 850));
+        assert_eq!(overload.minV.en, 0);
+        assert_eq!(OverloadConfig::from(&overload), config);
+
+        let config = OverloadConfig { max_current: Some(25.0), ..Default::default() };
+        assert!(matches!(config.to_overload(&threshold), Err(SonoffError::InvalidValue(_))));
+        let config = OverloadConfig { min_voltage: Some(230.0), max_voltage: Some(200.0), ..Default::default() };
+        assert!(matches!(config.to_overload(&threshold), Err(SonoffError::InvalidValue(_))));
+    }
+
+    #[tokio::test]
+    async fn test_set_overload() {
+        // The `/overload` request shape is assumed, the endpoint is not documented
+        let mut data = subdev_state();
+        data["overload_02"]["maxAP"] = json!({ "en": 1, "val": 200000 });
+        data["overload_02"]["maxC"] = json!({ "en": 1, "val": 1000 });
+        data["overload_02"]["delayTime"] = json!(3);
+        let mock = Arc::new(MockTransport::with_data(data));
+        let spm = SonoffPowerMeter::from(&SonoffDevice::with_transport(mock.clone()));
+        let current = OverloadConfig { max_power: Some(2000.0), max_current: Some(10.0), delay: 3, ..Default::default() };
+        assert_eq!(spm.get_overload("01000a1b2c".to_owned(), 2).await.unwrap(), current);
+        assert_eq!(spm.get_overload("01000a1b2c".to_owned(), 1).await.unwrap(), OverloadConfig::default());
+
+        let updated = spm.update_overload("01000a1b2c".to_owned(), 2, |config| config.max_power = None).await.unwrap();
+        assert_eq!(updated, OverloadConfig { max_power: None, ..current });
+        let requests = mock.requests();
+        assert_eq!(requests.last().unwrap().0, "/overload");
+        let req = &requests.last().unwrap().1["data"];
+        assert_eq!((&req["subDevId"], &req["outlet"], &req["delayTime"]), (&json!("01000a1b2c"), &json!(2), &json!(3)));
+        assert_eq!(req["maxAP"], json!({ "en": 0, "val": 0 }));
+        assert_eq!(req["maxC"], json!({ "en": 1, "val": 1000 }));
+
+        let sent = mock.requests().len();
+        let too_high = OverloadConfig { max_current: Some(21.0), ..Default::default() };
+        assert!(spm.set_overload("01000a1b2c".to_owned(), 2, too_high).await.is_err());
+        assert!(spm.set_overload("01000a1b2c".to_owned(), 4, current).await.is_err());
+        // Only the status is fetched for the rejected limits
+        assert_eq!(mock.requests().len(), sent + 1);
+    }
 }
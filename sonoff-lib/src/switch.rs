@@ -1,9 +1,11 @@
-use anyhow::Result;
 use async_trait::async_trait;
 use serde::{Serialize, Deserialize};
 
 use crate::device::{SonoffDevice, DevRes};
-use crate::switchable::SonoffSwitchable;
+use crate::error::Result;
+use crate::multi_switchable::{toggle_locked, SonoffMultiSwitchable};
+use crate::switchable::{SonoffSwitchable, SwitchState, StartupState, PulseState};
+use crate::values::PulseWidth;
 
 // JSON models
 // ===================================================================
@@ -11,16 +13,16 @@ use crate::switchable::SonoffSwitchable;
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct SonoffSwitchInfo {
-    pub switch: String,
-    pub startup: String,
-    pub pulse: String,
+    pub switch: SwitchState,
+    pub startup: StartupState,
+    pub pulse: PulseState,
     pub pulse_width: u32,
 }
 
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct SonoffSwitchPulseReq {
-    pub pulse: String,
+    pub pulse: PulseState,
     pub pulse_width: u32,
 }
 
@@ -43,24 +45,86 @@ impl SonoffSwitchable for SonoffSwitch {
 }
 
 impl SonoffSwitch {
-    /// Only supports multiples of 500ms. Setting `0` deactivates pulse
-    pub async fn pulse(&self, milliseconds: u32) -> Result<DevRes> {
+    /// Turn the switch off `width` after every time it is turned on
+    pub async fn pulse(&self, width: PulseWidth) -> Result<DevRes> {
         let req_obj = SonoffSwitchPulseReq {
-            pulse: if milliseconds == 0 { "off" } else { "on" }.to_owned(),
-            pulse_width: milliseconds,
+            pulse: PulseState::On,
+            pulse_width: width.get(),
         };
         self.get_dev().__request("/pulse", req_obj).await
     }
+
+    /// Deactivate pulse
+    pub async fn pulse_off(&self) -> Result<DevRes> {
+        let req_obj = SonoffSwitchPulseReq {
+            pulse: PulseState::Off,
+            pulse_width: 0,
+        };
+        self.get_dev().__request("/pulse", req_obj).await
+    }
+}
+
+/// Single outlet (0), for code that also drives multi-channel relays
+#[async_trait]
+impl SonoffMultiSwitchable for SonoffSwitch {
+    fn outlet_count(&self) -> u8 { 1 }
+
+    async fn get_outlets(&self) -> Result<Vec<bool>> {
+        Ok(vec![self.get_switch().await?])
+    }
+
+    async fn set_outlet(&self, outlet: u8, state: SwitchState) -> Result<DevRes> {
+        self.check_outlet(outlet)?;
+        self.set_switch(state).await
+    }
+
+    async fn set_outlet_startup(&self, outlet: u8, startup: StartupState) -> Result<DevRes> {
+        self.check_outlet(outlet)?;
+        self.set_startup(startup).await
+    }
+
+    async fn set_outlet_pulse(&self, outlet: u8, width: Option<PulseWidth>) -> Result<DevRes> {
+        self.check_outlet(outlet)?;
+        match width {
+            Some(width) => self.pulse(width).await,
+            None => self.pulse_off().await,
+        }
+    }
+
+    async fn outlet_toggle(&self, outlet: u8) -> Result<DevRes> {
+        toggle_locked(self, &self.dev, outlet).await
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::sync::Arc;
+    use serde_json::json;
     use super::*;
+    use crate::transport::MockTransport;
 
     #[tokio::test]
     async fn test_switch_on() {
-        let dev = SonoffDevice::new("http://127.0.0.1:8081");
+        let mock = Arc::new(MockTransport::with_data(json!({})));
+        let dev = SonoffDevice::with_transport(mock.clone());
         let switch = SonoffSwitch::from(&dev);
         switch.on().await.unwrap();
+        assert_eq!(mock.requests(), vec![
+            ("/switch".to_owned(), json!({ "deviceId": "", "data": { "switch": "on" } })),
+        ]);
+    }
+
+    #[test]
+    fn test_switch_info() {
+        let info: SonoffSwitchInfo = serde_json::from_value(json!({
+            "switch": "off", "startup": "stay", "pulse": "on", "pulseWidth": 1500,
+        })).unwrap();
+        assert_eq!(info.switch, SwitchState::Off);
+        assert_eq!(info.startup, StartupState::Stay);
+        assert_eq!(info.pulse, PulseState::On);
+        let err = serde_json::from_value::<SonoffSwitchInfo>(json!({
+            "switch": "off", "startup": "stya", "pulse": "on", "pulseWidth": 1500,
+        }));
+        assert!(err.is_err());
     }
 }
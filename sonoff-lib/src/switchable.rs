@@ -1,16 +1,85 @@
-use anyhow::Result;
 use async_trait::async_trait;
 use serde::{Serialize, Deserialize};
 
 use crate::device::{SonoffDevice, DevRes};
+use crate::error::Result;
 
 // JSON models
 // ===================================================================
 
+/// Switch state ("on" or "off")
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum SwitchState {
+    On,
+    Off,
+}
+
+/// State for when the device restarts ("on", "off", or "stay" for the last
+/// known state)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum StartupState {
+    On,
+    Off,
+    Stay,
+}
+
+/// Whether the switch turns itself off after the pulse width ("on" or
+/// "off")
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum PulseState {
+    On,
+    Off,
+}
+
+impl From<bool> for SwitchState {
+    fn from(value: bool) -> Self {
+        if value { SwitchState::On } else { SwitchState::Off }
+    }
+}
+
+impl From<SwitchState> for bool {
+    fn from(value: SwitchState) -> Self {
+        value == SwitchState::On
+    }
+}
+
+impl std::fmt::Display for SwitchState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            SwitchState::On => "on",
+            SwitchState::Off => "off",
+        })
+    }
+}
+
+impl std::fmt::Display for StartupState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            StartupState::On => "on",
+            StartupState::Off => "off",
+            StartupState::Stay => "stay",
+        })
+    }
+}
+
+impl std::fmt::Display for PulseState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            PulseState::On => "on",
+            PulseState::Off => "off",
+        })
+    }
+}
+
 #[derive(Debug, Serialize)]
 pub struct SonoffSwitchReq {
-    /// Switch state: "on" or "off"
-    pub switch: String,
+    pub switch: SwitchState,
 }
 
 #[derive(Debug, Serialize)]
@@ -18,13 +87,12 @@ pub struct SonoffSwitchStateReq { }
 
 #[derive(Debug, Deserialize)]
 pub struct SonoffSwitchState {
-    pub switch: String,
+    pub switch: SwitchState,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SonoffSwitchStartupReq {
-    /// Startup state: "on", "off", or "stay" for the last known state
-    pub startup: String,
+    pub startup: StartupState,
 }
 
 // Implementation
@@ -37,21 +105,20 @@ pub trait SonoffSwitchable {
     async fn get_switch(&self) -> Result<bool> {
         let req_obj = SonoffSwitchStateReq { };
         let state: SonoffSwitchState = self.get_dev().request("/info", req_obj).await?;
-        Ok(state.switch == "on")
+        Ok(state.switch.into())
     }
 
-    /// Valid state: "on", "off".
-    async fn set_switch(&self, state: impl Into<String> + Send) -> Result<DevRes> {
-        let req_obj = SonoffSwitchReq { switch: state.into() };
+    async fn set_switch(&self, state: SwitchState) -> Result<DevRes> {
+        let req_obj = SonoffSwitchReq { switch: state };
         self.get_dev().__request("/switch", req_obj).await
     }
 
     async fn on(&self) -> Result<DevRes> {
-        self.set_switch("on").await
+        self.set_switch(SwitchState::On).await
     }
 
     async fn off(&self) -> Result<DevRes> {
-        self.set_switch("off").await
+        self.set_switch(SwitchState::Off).await
     }
 
     async fn toggle(&self) -> Result<DevRes> {
@@ -63,10 +130,9 @@ pub trait SonoffSwitchable {
     }
 
     /// Set the state for when the device restarts (e.g. after a power loss).
-    /// Valid values are: "on", "off", and "stay" for the previous known state.
     /// 
     /// NOTE: Bulbs do NOT support this.
-    async fn set_startup(&self, state: String) -> Result<DevRes> {
+    async fn set_startup(&self, state: StartupState) -> Result<DevRes> {
         let req_obj = SonoffSwitchStartupReq { startup: state };
         self.get_dev().__request("/startup", req_obj).await
     }
@@ -0,0 +1,160 @@
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+
+use crate::client::SonoffClient;
+use crate::error::{Result, SonoffError};
+
+// Implementation
+// ===================================================================
+
+/// Carries requests to a device and brings back its replies.
+///
+/// `url_path` is the endpoint below `/zeroconf` (e.g. "/switch") and `body`
+/// is the whole request object (`DevReq`). The reply is the whole response
+/// object (`DevRes`), still unchecked.
+#[async_trait]
+pub trait Transport: Send + Sync {
+    async fn send(&self, url_path: &str, body: serde_json::Value) -> Result<serde_json::Value>;
+
+    /// Base address of the device (e.g. "http://192.168.1.2:8081"), if the
+    /// transport talks to one over the network
+    fn address(&self) -> Option<&str> { None }
+}
+
+#[async_trait]
+impl<T: Transport + ?Sized> Transport for Arc<T> {
+    async fn send(&self, url_path: &str, body: serde_json::Value) -> Result<serde_json::Value> {
+        (**self).send(url_path, body).await
+    }
+
+    fn address(&self) -> Option<&str> { (**self).address() }
+}
+
+/// Plain HTTP, as spoken by devices in DIY mode.
+pub struct HttpTransport {
+    client: SonoffClient,
+    address: String,
+}
+
+impl HttpTransport {
+    pub fn new(client: SonoffClient, address: impl Into<String>) -> HttpTransport {
+        HttpTransport { client, address: address.into() }
+    }
+}
+
+#[async_trait]
+impl Transport for HttpTransport {
+    async fn send(&self, url_path: &str, body: serde_json::Value) -> Result<serde_json::Value> {
+        let url = format!("{}/zeroconf{url_path}", self.address);
+        let res = self.client.post(url).body(serde_json::to_string(&body)?)
+            .send().await?;
+        let status = res.status();
+        if !status.is_success() {
+            return Err(SonoffError::HttpStatus(status));
+        }
+        let res = res.text().await?;
+        Ok(serde_json::from_str(&res)?)
+    }
+
+    fn address(&self) -> Option<&str> { Some(&self.address) }
+}
+
+type MockHandler = dyn Fn(&str, &serde_json::Value) -> serde_json::Value + Send + Sync;
+
+/// In-memory device. Every request is answered by `handler` and recorded.
+pub struct MockTransport {
+    handler: Box<MockHandler>,
+    requests: Mutex<Vec<(String, serde_json::Value)>>,
+}
+
+impl MockTransport {
+    pub fn new<F>(handler: F) -> MockTransport
+    where
+        F: Fn(&str, &serde_json::Value) -> serde_json::Value + Send + Sync + 'static
+    {
+        MockTransport { handler: Box::new(handler), requests: Mutex::new(Vec::new()) }
+    }
+
+    /// Device that answers every request with success and `data`
+    pub fn with_data(data: serde_json::Value) -> MockTransport {
+        MockTransport::new(move |_, _| serde_json::json!({ "seq": 1, "error": 0, "data": data }))
+    }
+
+    /// Requests received so far, as (url_path, body)
+    pub fn requests(&self) -> Vec<(String, serde_json::Value)> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl Transport for MockTransport {
+    async fn send(&self, url_path: &str, body: serde_json::Value) -> Result<serde_json::Value> {
+        let res = (self.handler)(url_path, &body);
+        self.requests.lock().unwrap().push((url_path.to_owned(), body));
+        Ok(res)
+    }
+}
+
+/// Wraps another transport and records every request and reply.
+pub struct RecordingTransport {
+    inner: Arc<dyn Transport>,
+    log: Mutex<Vec<Exchange>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Exchange {
+    pub url_path: String,
+    pub request: serde_json::Value,
+    /// Reply, or the error message when the request failed
+    pub response: std::result::Result<serde_json::Value, String>,
+}
+
+impl RecordingTransport {
+    pub fn new(inner: impl Transport + 'static) -> RecordingTransport {
+        RecordingTransport { inner: Arc::new(inner), log: Mutex::new(Vec::new()) }
+    }
+
+    pub fn log(&self) -> Vec<Exchange> {
+        self.log.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl Transport for RecordingTransport {
+    async fn send(&self, url_path: &str, body: serde_json::Value) -> Result<serde_json::Value> {
+        let res = self.inner.send(url_path, body.clone()).await;
+        self.log.lock().unwrap().push(Exchange {
+            url_path: url_path.to_owned(),
+            request: body,
+            response: res.as_ref().map(|v| v.clone()).map_err(|e| e.to_string()),
+        });
+        res
+    }
+
+    fn address(&self) -> Option<&str> { self.inner.address() }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use super::*;
+    use crate::device::SonoffDevice;
+
+    #[tokio::test]
+    async fn test_recording() {
+        let mock = MockTransport::new(|url_path, _| match url_path {
+            "/info" => json!({ "seq": 1, "error": 0, "data": { "deviceid": "1000abcdef" } }),
+            _ => json!({ "seq": 2, "error": 404 }),
+        });
+        let recorder = Arc::new(RecordingTransport::new(mock));
+        let dev = SonoffDevice::with_transport(recorder.clone());
+        dev.get_info().await.unwrap();
+        dev.set_wifi("home".to_owned(), "secret".to_owned()).await.unwrap_err();
+        let log = recorder.log();
+        assert_eq!(log.len(), 2);
+        assert_eq!(log[0].url_path, "/info");
+        assert_eq!(log[1].request["data"]["ssid"], "home");
+        assert_eq!(log[1].response.as_ref().unwrap()["error"], 404);
+    }
+}
@@ -0,0 +1,176 @@
+use serde::{Serialize, Deserialize};
+
+use crate::error::{Result, SonoffError};
+
+pub(crate) fn check_range<T>(name: &str, value: T, min: T, max: T) -> Result<T>
+where
+    T: PartialOrd + std::fmt::Display + Copy
+{
+    // Also rejects NaN, which is neither lower nor greater than anything
+    if !(min..=max).contains(&value) {
+        return Err(SonoffError::InvalidValue(format!("{name} must be between {min} and {max}, got {value}")));
+    }
+    Ok(value)
+}
+
+/// Brightness in percent (min=0, max=100). Bulbs need at least
+/// `bulb::BRIGHTNESS_MIN`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(try_from = "u8", into = "u8")]
+pub struct Brightness(u8);
+
+impl Brightness {
+    pub const MIN: u8 = 0;
+    pub const MAX: u8 = 100;
+
+    pub fn get(self) -> u8 { self.0 }
+
+    /// Clamps a value reported by the device, which is not validated
+    pub(crate) fn saturating(value: u8) -> Brightness {
+        Brightness(value.min(Brightness::MAX))
+    }
+}
+
+impl TryFrom<u8> for Brightness {
+    type Error = SonoffError;
+
+    fn try_from(value: u8) -> Result<Self> {
+        check_range("brightness", value, Brightness::MIN, Brightness::MAX).map(Brightness)
+    }
+}
+
+impl From<Brightness> for u8 {
+    fn from(value: Brightness) -> Self { value.0 }
+}
+
+/// Color temperature of white bulbs, from warm to cold (min=0, max=100)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(try_from = "u8", into = "u8")]
+pub struct ColorTemp(u8);
+
+impl ColorTemp {
+    pub const MIN: u8 = 0;
+    pub const MAX: u8 = 100;
+
+    pub fn get(self) -> u8 { self.0 }
+
+    /// Clamps a value reported by the device, which is not validated
+    pub(crate) fn saturating(value: u8) -> ColorTemp {
+        ColorTemp(value.min(ColorTemp::MAX))
+    }
+}
+
+impl TryFrom<u8> for ColorTemp {
+    type Error = SonoffError;
+
+    fn try_from(value: u8) -> Result<Self> {
+        check_range("color temperature", value, ColorTemp::MIN, ColorTemp::MAX).map(ColorTemp)
+    }
+}
+
+impl From<ColorTemp> for u8 {
+    fn from(value: ColorTemp) -> Self { value.0 }
+}
+
+/// Pulse width in milliseconds (multiple of 500, min=500, max=3600000)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(try_from = "u32", into = "u32")]
+pub struct PulseWidth(u32);
+
+impl PulseWidth {
+    pub const MIN: u32 = 500;
+    pub const MAX: u32 = 3_600_000;
+    pub const STEP: u32 = 500;
+
+    pub fn get(self) -> u32 { self.0 }
+}
+
+impl TryFrom<u32> for PulseWidth {
+    type Error = SonoffError;
+
+    fn try_from(value: u32) -> Result<Self> {
+        check_range("pulse width", value, PulseWidth::MIN, PulseWidth::MAX)?;
+        if value % PulseWidth::STEP != 0 {
+            return Err(SonoffError::InvalidValue(format!("pulse width must be a multiple of {}, got {value}", PulseWidth::STEP)));
+        }
+        Ok(PulseWidth(value))
+    }
+}
+
+impl From<PulseWidth> for u32 {
+    fn from(value: PulseWidth) -> Self { value.0 }
+}
+
+/// Color of color bulbs (min=1, max=255 for every channel)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Rgb {
+    r: u8,
+    g: u8,
+    b: u8,
+}
+
+impl Rgb {
+    pub const MIN: u8 = 1;
+    pub const MAX: u8 = 255;
+
+    pub fn new(r: u8, g: u8, b: u8) -> Result<Rgb> {
+        check_range("red", r, Rgb::MIN, Rgb::MAX)?;
+        check_range("green", g, Rgb::MIN, Rgb::MAX)?;
+        check_range("blue", b, Rgb::MIN, Rgb::MAX)?;
+        Ok(Rgb { r, g, b })
+    }
+
+    pub fn r(self) -> u8 { self.r }
+    pub fn g(self) -> u8 { self.g }
+    pub fn b(self) -> u8 { self.b }
+
+    /// Clamps a color reported by the device, which is not validated
+    pub(crate) fn saturating(r: u8, g: u8, b: u8) -> Rgb {
+        Rgb { r: r.max(Rgb::MIN), g: g.max(Rgb::MIN), b: b.max(Rgb::MIN) }
+    }
+}
+
+impl TryFrom<(u8, u8, u8)> for Rgb {
+    type Error = SonoffError;
+
+    fn try_from((r, g, b): (u8, u8, u8)) -> Result<Self> {
+        Rgb::new(r, g, b)
+    }
+}
+
+impl From<Rgb> for (u8, u8, u8) {
+    fn from(value: Rgb) -> Self { (value.r, value.g, value.b) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ranges() {
+        assert_eq!(Brightness::try_from(100).unwrap().get(), 100);
+        assert_eq!(Brightness::try_from(0).unwrap().get(), 0);
+        assert!(Brightness::try_from(101).is_err());
+        assert!(ColorTemp::try_from(0).is_ok());
+        assert!(ColorTemp::try_from(101).is_err());
+        assert_eq!(PulseWidth::try_from(1500).unwrap().get(), 1500);
+        assert!(PulseWidth::try_from(700).is_err());
+        assert!(PulseWidth::try_from(0).is_err());
+        assert!(PulseWidth::try_from(3_600_500).is_err());
+        let err = Brightness::try_from(101).unwrap_err();
+        assert_eq!(err.to_string(), "invalid value: brightness must be between 0 and 100, got 101");
+        assert_eq!(Brightness::saturating(255).get(), 100);
+        assert_eq!(ColorTemp::saturating(20).get(), 20);
+        assert!(Rgb::try_from((255, 1, 1)).is_ok());
+        let err = Rgb::new(255, 0, 1).unwrap_err();
+        assert_eq!(err.to_string(), "invalid value: green must be between 1 and 255, got 0");
+        assert_eq!(Rgb::saturating(255, 0, 0), Rgb::new(255, 1, 1).unwrap());
+    }
+
+    #[test]
+    fn test_serde() {
+        let br: Brightness = serde_json::from_str("40").unwrap();
+        assert_eq!(serde_json::to_string(&br).unwrap(), "40");
+        assert!(serde_json::from_str::<Brightness>("101").is_err());
+    }
+}
@@ -0,0 +1,358 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use futures::Stream;
+use mdns_sd::{ServiceDaemon, ServiceEvent};
+
+use crate::discovery::{DiscoveredDevice, SERVICE_TYPE};
+use crate::error::Result;
+
+// Models
+// ===================================================================
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceEvent {
+    /// Device ID (e.g. "1000abcdef")
+    pub id: String,
+    /// `seq` of the announcement that carried the change
+    pub seq: Option<u32>,
+    pub change: StateChange,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StateChange {
+    /// First announcement of the device since watching started, or since it
+    /// went offline or restarted its `seq` counter (e.g. after a reboot)
+    Online(DiscoveredDevice),
+    /// The device stopped announcing itself
+    Offline,
+    /// Switch turned on (`true`) or off (`false`)
+    Switch(bool),
+    /// Outlet of a multi-channel device turned on (`true`) or off (`false`)
+    Outlet { outlet: u8, on: bool },
+    /// Brightness of a dimmer or bulb changed
+    Brightness(u8),
+    /// Any other field of the announced state changed
+    Other { key: String, value: serde_json::Value },
+    /// A field is no longer part of the announced state
+    Removed { key: String },
+}
+
+// Implementation
+// ===================================================================
+
+struct KnownDevice {
+    fullname: String,
+    seq: Option<u32>,
+    data: serde_json::Map<String, serde_json::Value>,
+    /// When the last announcement was accepted
+    updated: Instant,
+    /// Cleared when the device stops announcing itself. Its last state is
+    /// kept to report what changed when it comes back.
+    online: bool,
+}
+
+/// Announcements at most this far behind the last `seq`, and arriving
+/// within `SEQ_REORDER_TIME` of it, are repeated or out of order. Otherwise
+/// the device restarted its counter (e.g. after a reboot).
+const SEQ_REORDER_WINDOW: u32 = 16;
+const SEQ_REORDER_TIME: Duration = Duration::from_secs(2);
+
+/// Keeps the last announcement of every device and turns new ones into
+/// events.
+#[derive(Default)]
+struct Tracker {
+    devices: HashMap<String, KnownDevice>,
+    /// Device keys of encrypted devices, by device ID
+    keys: HashMap<String, String>,
+}
+
+impl Tracker {
+    fn update(&mut self, fullname: &str, found: DiscoveredDevice, now: Instant) -> Vec<DeviceEvent> {
+        let found = match self.keys.get(&found.id) {
+            Some(key) => found.decrypted(key).unwrap_or(found),
+            None => found,
+        };
+        // Undecodable states (e.g. encrypted ones) still count as online
+        let data = match found.data_json() {
+            Ok(serde_json::Value::Object(data)) => data,
+            _ => serde_json::Map::new(),
+        };
+        let event = |change| DeviceEvent { id: found.id.to_owned(), seq: found.seq, change };
+        let Some(known) = self.devices.get_mut(&found.id) else {
+            self.devices.insert(found.id.to_owned(), KnownDevice {
+                fullname: fullname.to_owned(),
+                seq: found.seq,
+                data,
+                updated: now,
+                online: true,
+            });
+            return vec![event(StateChange::Online(found.to_owned()))];
+        };
+        let restarted = match (known.seq, found.seq) {
+            (Some(last), Some(seq)) if known.online && seq <= last => {
+                // Announcements are repeated and may arrive out of order, but
+                // not long after the newer one
+                if last - seq <= SEQ_REORDER_WINDOW && now.duration_since(known.updated) <= SEQ_REORDER_TIME {
+                    return Vec::new();
+                }
+                true
+            },
+            _ => false,
+        };
+        let mut events = Vec::new();
+        if !known.online || restarted {
+            known.online = true;
+            known.fullname = fullname.to_owned();
+            events.push(event(StateChange::Online(found.to_owned())));
+        }
+        known.seq = found.seq;
+        known.updated = now;
+        let changes = diff(&known.data, &data);
+        known.data = data;
+        events.extend(changes.into_iter().map(event));
+        events
+    }
+
+    fn remove(&mut self, fullname: &str) -> Option<DeviceEvent> {
+        let (id, known) = self.devices.iter_mut()
+            .find(|(_, known)| known.online && known.fullname == fullname)?;
+        known.online = false;
+        Some(DeviceEvent { id: id.to_owned(), seq: known.seq, change: StateChange::Offline })
+    }
+}
+
+fn diff(
+    old: &serde_json::Map<String, serde_json::Value>,
+    new: &serde_json::Map<String, serde_json::Value>,
+) -> Vec<StateChange> {
+    let mut changes = Vec::new();
+    for (key, value) in new {
+        let old_value = old.get(key);
+        if old_value == Some(value) {
+            continue;
+        }
+        match (key.as_str(), value) {
+            ("switch", serde_json::Value::String(state)) => {
+                changes.push(StateChange::Switch(state == "on"));
+            },
+            ("brightness", value) if brightness(value).is_some() => {
+                changes.extend(brightness(value).map(StateChange::Brightness));
+            },
+            ("switches", serde_json::Value::Array(outlets)) => {
+                let old_outlets = old_value.and_then(|v| v.as_array());
+                for outlet in outlets {
+                    if old_outlets.is_some_and(|old| old.contains(outlet)) {
+                        continue;
+                    }
+                    if let (Some(num), Some(state)) = (outlet["outlet"].as_u64(), outlet["switch"].as_str()) {
+                        changes.push(StateChange::Outlet { outlet: num as u8, on: state == "on" });
+                    }
+                }
+            },
+            // Bulbs announce brightness inside the object of the current
+            // lamp type (e.g. "color", "white", or a scene)
+            (_, value) if new.get("ltype").and_then(|ltype| ltype.as_str()) == Some(key)
+                && brightness(&value["br"]).is_some()
+                && old_value.map(|old| &old["br"]) != Some(&value["br"]) => {
+                changes.extend(brightness(&value["br"]).map(StateChange::Brightness));
+                changes.push(StateChange::Other { key: key.to_owned(), value: value.to_owned() });
+            },
+            _ => changes.push(StateChange::Other { key: key.to_owned(), value: value.to_owned() }),
+        }
+    }
+    for key in old.keys().filter(|key| !new.contains_key(*key)) {
+        changes.push(StateChange::Removed { key: key.to_owned() });
+    }
+    changes
+}
+
+fn brightness(value: &serde_json::Value) -> Option<u8> {
+    value.as_u64().and_then(|br| u8::try_from(br).ok())
+}
+
+/// Stops the mDNS daemon when the stream is dropped
+struct Daemon(ServiceDaemon);
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        let _ = self.0.shutdown();
+    }
+}
+
+/// Watches the local network for state changes announced by devices.
+///
+/// The first announcement of every device is reported as
+/// `StateChange::Online`. After that, every announcement with a newer `seq`
+/// is compared against the previous one and reported as one event per
+/// changed field. Announcements slightly behind the last `seq` and arriving
+/// shortly after it are dropped as repeated or out of order. A device that
+/// comes back after going offline, or whose `seq` goes back otherwise (e.g.
+/// after a reboot), is reported online again along with what changed
+/// meanwhile.
+pub fn watch() -> Result<impl Stream<Item = DeviceEvent>> {
+    watch_with_keys(HashMap::new())
+}
+
+/// Like `watch`, but also decrypts the announcements of devices in eWeLink
+/// LAN mode. `keys` maps device IDs to device keys.
+pub fn watch_with_keys(keys: HashMap<String, String>) -> Result<impl Stream<Item = DeviceEvent>> {
+    let mdns = ServiceDaemon::new()?;
+    let receiver = mdns.browse(SERVICE_TYPE)?;
+    let tracker = Tracker { devices: HashMap::new(), keys };
+    let state = (Daemon(mdns), receiver, tracker, VecDeque::new());
+    Ok(futures::stream::unfold(state, |(mdns, receiver, mut tracker, mut pending)| async move {
+        loop {
+            if let Some(event) = pending.pop_front() {
+                return Some((event, (mdns, receiver, tracker, pending)));
+            }
+            match receiver.recv_async().await.ok()? {
+                ServiceEvent::ServiceResolved(info) => {
+                    if let Some(found) = DiscoveredDevice::from_service_info(&info) {
+                        pending.extend(tracker.update(info.get_fullname(), found, Instant::now()));
+                    }
+                },
+                ServiceEvent::ServiceRemoved(_, fullname) => {
+                    pending.extend(tracker.remove(&fullname));
+                },
+                _ => {},
+            }
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn announce(seq: u32, data: &str) -> DiscoveredDevice {
+        DiscoveredDevice {
+            id: "1000abcdef".to_owned(),
+            device_type: "strip".to_owned(),
+            apivers: Some(1),
+            txtvers: Some(1),
+            seq: Some(seq),
+            data: data.to_owned(),
+            encrypted: false,
+            iv: None,
+            addresses: vec!["192.168.1.2".parse().unwrap()],
+            port: 8081,
+        }
+    }
+
+    #[test]
+    fn test_tracker() {
+        let fullname = "eWeLink_1000abcdef._ewelink._tcp.local.";
+        let now = Instant::now();
+        let mut tracker = Tracker::default();
+        let first = announce(1, r#"{"switches":[{"outlet":0,"switch":"off"},{"outlet":1,"switch":"off"}]}"#);
+        let events = tracker.update(fullname, first.clone(), now);
+        assert_eq!(events[0].change, StateChange::Online(first));
+
+        let events = tracker.update(fullname, announce(3, r#"{"switches":[{"outlet":0,"switch":"off"},{"outlet":1,"switch":"on"}]}"#), now);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].seq, Some(3));
+        assert_eq!(events[0].change, StateChange::Outlet { outlet: 1, on: true });
+
+        // Stale announcement
+        let events = tracker.update(fullname, announce(2, r#"{"switches":[{"outlet":0,"switch":"on"},{"outlet":1,"switch":"on"}]}"#), now);
+        assert!(events.is_empty());
+
+        let event = tracker.remove(fullname).unwrap();
+        assert_eq!(event.change, StateChange::Offline);
+        assert!(tracker.remove(fullname).is_none());
+
+        // Back online with an older seq, compared against the last state
+        let back = announce(2, r#"{"switches":[{"outlet":0,"switch":"on"},{"outlet":1,"switch":"on"}]}"#);
+        let events = tracker.update(fullname, back.clone(), now);
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].change, StateChange::Online(back.clone()));
+        assert_eq!(events[1].change, StateChange::Outlet { outlet: 0, on: true });
+        assert!(tracker.update(fullname, back, now).is_empty());
+    }
+
+    #[test]
+    fn test_tracker_reboot() {
+        let fullname = "eWeLink_1000abcdef._ewelink._tcp.local.";
+        let now = Instant::now();
+        let mut tracker = Tracker::default();
+        tracker.update(fullname, announce(5000, r#"{"switch":"on"}"#), now);
+        assert!(tracker.update(fullname, announce(5000 - SEQ_REORDER_WINDOW, r#"{"switch":"off"}"#), now).is_empty());
+
+        // The counter restarted
+        let rebooted = announce(1, r#"{"switch":"off"}"#);
+        let events = tracker.update(fullname, rebooted.clone(), now);
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].change, StateChange::Online(rebooted));
+        assert_eq!(events[1].seq, Some(1));
+        assert_eq!(events[1].change, StateChange::Switch(false));
+        let events = tracker.update(fullname, announce(2, r#"{"switch":"on"}"#), now);
+        assert_eq!(events, vec![DeviceEvent {
+            id: "1000abcdef".to_owned(),
+            seq: Some(2),
+            change: StateChange::Switch(true),
+        }]);
+    }
+
+    #[test]
+    fn test_tracker_reboot_within_window() {
+        let fullname = "eWeLink_1000abcdef._ewelink._tcp.local.";
+        let now = Instant::now();
+        let mut tracker = Tracker::default();
+        tracker.update(fullname, announce(5, r#"{"switch":"on"}"#), now);
+
+        // Too late to be out of order, so the counter restarted
+        let later = now + SEQ_REORDER_TIME + Duration::from_millis(1);
+        let rebooted = announce(1, r#"{"switch":"off"}"#);
+        let events = tracker.update(fullname, rebooted.clone(), later);
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].change, StateChange::Online(rebooted));
+        assert_eq!(events[1].change, StateChange::Switch(false));
+        let events = tracker.update(fullname, announce(2, r#"{"switch":"on"}"#), later);
+        assert_eq!(events[0].change, StateChange::Switch(true));
+
+        // Repeated shortly after
+        assert!(tracker.update(fullname, announce(1, r#"{"switch":"off"}"#), later).is_empty());
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = serde_json::from_str(r#"{"switch":"off","brightness":10}"#).unwrap();
+        let new = serde_json::from_str(r#"{"switch":"on","brightness":60}"#).unwrap();
+        assert_eq!(diff(&old, &new), vec![StateChange::Brightness(60), StateChange::Switch(true)]);
+
+        let old = serde_json::from_str(r#"{"switch":"on","pulse":"on"}"#).unwrap();
+        let new = serde_json::from_str(r#"{"switch":"on"}"#).unwrap();
+        assert_eq!(diff(&old, &new), vec![StateChange::Removed { key: "pulse".to_owned() }]);
+
+        let old = serde_json::from_str(r#"{"ltype":"white","white":{"br":10,"ct":0}}"#).unwrap();
+        let new = serde_json::from_str(r#"{"ltype":"white","white":{"br":60,"ct":0}}"#).unwrap();
+        assert_eq!(diff(&old, &new)[0], StateChange::Brightness(60));
+
+        // Out of range or malformed brightness is not reported as such
+        let old = serde_json::from_str(r#"{"brightness":10}"#).unwrap();
+        let new = serde_json::from_str(r#"{"brightness":300}"#).unwrap();
+        assert_eq!(diff(&old, &new), vec![StateChange::Other { key: "brightness".to_owned(), value: 300.into() }]);
+        let old = serde_json::from_str(r#"{"ltype":"white","white":{"br":10,"ct":0}}"#).unwrap();
+        let new = serde_json::from_str(r#"{"ltype":"white","white":{"br":"x","ct":0}}"#).unwrap();
+        assert!(!diff(&old, &new).iter().any(|change| matches!(change, StateChange::Brightness(_))));
+    }
+
+    #[test]
+    fn test_diff_bulb() {
+        // Switching to a scene changes the lamp type and the brightness
+        let old = serde_json::from_str(r#"{"ltype":"white","white":{"br":10,"ct":0}}"#).unwrap();
+        let new = serde_json::from_str(r#"{"ltype":"read","read":{"br":60,"r":255,"g":255,"b":255}}"#).unwrap();
+        let changes = diff(&old, &new);
+        assert!(changes.contains(&StateChange::Brightness(60)));
+        assert!(changes.contains(&StateChange::Other { key: "ltype".to_owned(), value: "read".into() }));
+        assert!(changes.contains(&StateChange::Removed { key: "white".to_owned() }));
+
+        // Objects other than the one of the lamp type are not brightness
+        let old = serde_json::from_str(r#"{"ltype":"white","white":{"br":10,"ct":0}}"#).unwrap();
+        let new = serde_json::from_str(r#"{"ltype":"white","white":{"br":10,"ct":0},"night":{"br":5}}"#).unwrap();
+        assert_eq!(diff(&old, &new), vec![
+            StateChange::Other { key: "night".to_owned(), value: serde_json::json!({ "br": 5 }) },
+        ]);
+    }
+}
@@ -0,0 +1,807 @@
+//! Simulator of the DIY mode HTTP API (`/zeroconf/...`) of Sonoff devices.
+//!
+//! State lives in memory for as long as the `Simulator` does.
+
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde_json::{json, Value};
+
+// Models
+// ===================================================================
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Model {
+    /// BASICR3/RFR3/MINI: single relay
+    BasicR3,
+    /// D1: dimmer
+    D1,
+    /// B05-BL: color bulb
+    B05Bl,
+    /// MINIR3: multi-channel relay (4 outlets)
+    MiniR3,
+    /// SPM-MAIN: power meter with one SPM-4Relay sub-device
+    SpmMain,
+}
+
+/// Request error, answered as the `error` field of the response
+const ERR_DEVICE_ID: u32 = 404;
+const ERR_PARAMS: u32 = 422;
+
+const MINI_R3_OUTLETS: usize = 4;
+const SPM_OUTLETS: usize = 4;
+const SPM_SUBDEV_ID: &str = "01000a1b2c";
+/// Not a documented code, the library reports it as is
+const SPM_SUBDEV_TYPE: u32 = 7;
+
+#[derive(Debug, Clone)]
+struct Outlet {
+    switch: String,
+    startup: String,
+    pulse: String,
+    width: u32,
+    /// SPM `rsn` codes of the last protection trip (see `Simulator::trip`),
+    /// cleared when turned on
+    tripped: Vec<u32>,
+}
+
+impl Default for Outlet {
+    fn default() -> Self {
+        Outlet {
+            switch: "off".to_owned(),
+            startup: "off".to_owned(),
+            pulse: "off".to_owned(),
+            width: 500,
+            tripped: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    model: Model,
+    deviceid: String,
+    seq: u32,
+    ssid: String,
+    /// Network indicator LED, on devices that have one
+    sled_online: String,
+    /// Single channel devices use the first outlet only
+    outlets: Vec<Outlet>,
+    // D1
+    brightness: u8,
+    mode: u8,
+    brightmin: u8,
+    brightmax: u8,
+    // B05-BL
+    ltype: String,
+    /// Settings of every lamp type ("color", "white", and scenes)
+    light: serde_json::Map<String, Value>,
+    // SPM-MAIN
+    /// Paired sub-device, if any. There is room for one on the bus.
+    sub_dev_id: Option<String>,
+    sub_outlets: Vec<Outlet>,
+}
+
+impl State {
+    fn new(model: Model) -> State {
+        let outlets = match model {
+            Model::MiniR3 => MINI_R3_OUTLETS,
+            Model::SpmMain => 0,
+            _ => 1,
+        };
+        State {
+            model,
+            deviceid: "1000abcdef".to_owned(),
+            seq: 1,
+            ssid: "sonoff-sim".to_owned(),
+            sled_online: "on".to_owned(),
+            outlets: vec![Outlet::default(); outlets],
+            brightness: 50,
+            mode: 0,
+            brightmin: 0,
+            brightmax: 100,
+            ltype: "white".to_owned(),
+            light: serde_json::Map::from_iter([
+                ("color".to_owned(), json!({ "br": 100, "r": 255, "g": 255, "b": 255 })),
+                ("white".to_owned(), json!({ "br": 100, "ct": 50 })),
+            ]),
+            sub_dev_id: (model == Model::SpmMain).then(|| SPM_SUBDEV_ID.to_owned()),
+            sub_outlets: if model == Model::SpmMain {
+                vec![Outlet::default(); SPM_OUTLETS]
+            } else {
+                Vec::new()
+            },
+        }
+    }
+
+    fn info(&self) -> Value {
+        let mut info = json!({
+            "deviceid": self.deviceid,
+            "bssid": "00:11:22:33:44:55",
+            "ssid": self.ssid,
+            "signalStrength": -55,
+            "fwVersion": "3.7.0",
+            "otaUnlock": false,
+        });
+        let per_device = match self.model {
+            Model::BasicR3 => {
+                let outlet = &self.outlets[0];
+                json!({
+                    "switch": outlet.switch,
+                    "startup": outlet.startup,
+                    "pulse": outlet.pulse,
+                    "pulseWidth": outlet.width,
+                })
+            },
+            Model::D1 => json!({
+                "switch": self.outlets[0].switch,
+                "startup": self.outlets[0].startup,
+                "brightness": self.brightness,
+                "mode": self.mode,
+                "brightmin": self.brightmin,
+                "brightmax": self.brightmax,
+            }),
+            Model::B05Bl => json!({
+                "switch": self.outlets[0].switch,
+                "ltype": self.ltype,
+                self.ltype.clone(): self.light[&self.ltype],
+            }),
+            Model::MiniR3 => json!({
+                "switches": self.switches(&self.outlets),
+                "configure": self.outlets.iter().enumerate()
+                    .map(|(i, o)| json!({ "outlet": i, "startup": o.startup }))
+                    .collect::<Vec<_>>(),
+                "pulses": self.outlets.iter().enumerate()
+                    .map(|(i, o)| json!({ "outlet": i, "pulse": o.pulse, "switch": "off", "width": o.width }))
+                    .collect::<Vec<_>>(),
+            }),
+            Model::SpmMain => json!({}),
+        };
+        merge(&mut info, per_device);
+        // SPM-MAIN reports it with `/getState`
+        if matches!(self.model, Model::BasicR3 | Model::D1 | Model::MiniR3) {
+            merge(&mut info, json!({ "sledOnline": self.sled_online }));
+        }
+        info
+    }
+
+    fn switches(&self, outlets: &[Outlet]) -> Vec<Value> {
+        outlets.iter().enumerate()
+            .map(|(i, o)| json!({ "outlet": i, "switch": o.switch }))
+            .collect()
+    }
+
+    fn spm_state(&self) -> Value {
+        json!({
+            "deviceid": self.deviceid,
+            "sledOnline": self.sled_online,
+            "ssid": self.ssid,
+            "bssid": "00:11:22:33:44:55",
+            "fwVersion": "1.1.0",
+            "subChipFwVer": "1.0.0",
+            "signalStrength": -55,
+            "wifiConnected": true,
+        })
+    }
+
+    fn spm_subdev_state(&self) -> Value {
+        let mut state = json!({
+            "fwVersion": "1.0.0",
+            "switches": self.switches(&self.sub_outlets),
+            "configure": self.sub_outlets.iter().enumerate()
+                .map(|(i, o)| json!({ "outlet": i, "startup": o.startup }))
+                .collect::<Vec<_>>(),
+            "pulses": self.sub_outlets.iter().enumerate()
+                .map(|(i, o)| json!({ "outlet": i, "pulse": o.pulse, "width": o.width }))
+                .collect::<Vec<_>>(),
+            "faultState": {
+                "subDevCom": 0,
+                "cse7761Com": [1, 1, 1, 1],
+                "overloadTrig": self.sub_outlets.iter().enumerate()
+                    .filter(|(_, o)| !o.tripped.is_empty())
+                    .map(|(i, o)| json!({ "outlet": i, "rsn": o.tripped }))
+                    .collect::<Vec<_>>(),
+            },
+            "threshold": {
+                "actPow": { "min": 10, "max": 440000 },
+                "voltage": { "min": 10, "max": 24000 },
+                "current": { "min": 10, "max": 2000 },
+            },
+        });
+        for (i, outlet) in self.sub_outlets.iter().enumerate() {
+            let (current, voltage, act_pow, react_pow, apparent_pow) = spm_load(i, outlet);
+            merge(&mut state, json!({
+                format!("current_{i:02}"): current,
+                format!("voltage_{i:02}"): voltage,
+                format!("actPow_{i:02}"): act_pow,
+                format!("reactPow_{i:02}"): react_pow,
+                format!("apparentPow_{i:02}"): apparent_pow,
+            }));
+        }
+        // Protection limits are reported all disabled and cannot be changed
+        let disabled = json!({ "en": 0, "val": 0 });
+        for i in 0..self.sub_outlets.len() {
+            merge(&mut state, json!({
+                format!("overload_{i:02}"): {
+                    "minAP": disabled, "maxAP": disabled,
+                    "minV": disabled, "maxV": disabled, "maxC": disabled,
+                    "delayTime": 0,
+                },
+            }));
+        }
+        state
+    }
+
+    /// Handles a request and returns (error, data)
+    fn handle(&mut self, url_path: &str, req: &Value) -> Option<(u32, Option<Value>)> {
+        let device_id = req["deviceid"].as_str()
+            .or(req["deviceId"].as_str())
+            .unwrap_or_default();
+        if !device_id.is_empty() && device_id != self.deviceid {
+            return Some((ERR_DEVICE_ID, None));
+        }
+        let data = &req["data"];
+        let single = self.model != Model::MiniR3 && self.model != Model::SpmMain;
+        let res = match (url_path, self.model) {
+            ("/info", _) => Ok(Some(self.info())),
+            ("/wifi", _) => {
+                match (data["ssid"].as_str(), data["password"].as_str()) {
+                    (Some(ssid), Some(_)) => { self.ssid = ssid.to_owned(); Ok(None) },
+                    _ => Err(ERR_PARAMS),
+                }
+            },
+            ("/sledonoff", Model::BasicR3 | Model::D1 | Model::MiniR3 | Model::SpmMain) => {
+                switch_value(&data["sledOnline"]).map(|v| { self.sled_online = v; None })
+            },
+            ("/switch", _) if single => {
+                switch_value(&data["switch"]).map(|v| { self.outlets[0].switch = v; None })
+            },
+            ("/startup", Model::BasicR3 | Model::D1) => {
+                startup_value(&data["startup"]).map(|v| { self.outlets[0].startup = v; None })
+            },
+            ("/pulse", Model::BasicR3) => {
+                switch_value(&data["pulse"]).and_then(|pulse| {
+                    if pulse == "on" {
+                        self.outlets[0].width = pulse_width(&data["pulseWidth"])?;
+                    }
+                    self.outlets[0].pulse = pulse;
+                    Ok(None)
+                })
+            },
+            ("/dimmable", Model::D1) => self.dimmable_d1(data),
+            ("/dimmable", Model::B05Bl) => self.dimmable_bulb(data),
+            ("/switches", Model::MiniR3) => {
+                let outlets = &mut self.outlets;
+                for_each_outlet(&data["switches"], outlets.len(), |i, item| {
+                    switch_value(&item["switch"]).map(|v| outlets[i].switch = v)
+                })
+            },
+            ("/switches", Model::SpmMain) => {
+                if !self.is_sub_dev(&data["subDevId"]) {
+                    Err(ERR_DEVICE_ID)
+                } else {
+                    let outlets = &mut self.sub_outlets;
+                    for_each_outlet(&data["switches"], outlets.len(), |i, item| {
+                        switch_value(&item["switch"]).map(|v| {
+                            if v == "on" {
+                                outlets[i].tripped.clear();
+                            }
+                            outlets[i].switch = v;
+                        })
+                    })
+                }
+            },
+            ("/startups", Model::MiniR3) => {
+                let outlets = &mut self.outlets;
+                for_each_outlet(&data["configure"], outlets.len(), |i, item| {
+                    startup_value(&item["startup"]).map(|v| outlets[i].startup = v)
+                })
+            },
+            ("/startups", Model::SpmMain) => {
+                if !self.is_sub_dev(&data["subDevId"]) {
+                    Err(ERR_DEVICE_ID)
+                } else {
+                    let outlets = &mut self.sub_outlets;
+                    for_each_outlet(&data["configure"], outlets.len(), |i, item| {
+                        startup_value(&item["startup"]).map(|v| outlets[i].startup = v)
+                    })
+                }
+            },
+            ("/pulses", Model::SpmMain) => {
+                if !self.is_sub_dev(&data["subDevId"]) {
+                    Err(ERR_DEVICE_ID)
+                } else {
+                    let outlets = &mut self.sub_outlets;
+                    for_each_outlet(&data["pulses"], outlets.len(), |i, item| {
+                        let pulse = switch_value(&item["pulse"])?;
+                        if pulse == "on" {
+                            outlets[i].width = pulse_width(&item["width"])?;
+                        }
+                        outlets[i].pulse = pulse;
+                        Ok(())
+                    })
+                }
+            },
+            ("/pulses", Model::MiniR3) => {
+                let outlets = &mut self.outlets;
+                for_each_outlet(&data["pulses"], outlets.len(), |i, item| {
+                    let pulse = switch_value(&item["pulse"])?;
+                    if pulse == "on" {
+                        outlets[i].width = pulse_width(&item["width"])?;
+                    }
+                    outlets[i].pulse = pulse;
+                    Ok(())
+                })
+            },
+            ("/subDevList", Model::SpmMain) => Ok(Some(json!({
+                "subDevList": self.sub_dev_id.iter()
+                    .map(|id| json!({ "subDevId": id, "type": SPM_SUBDEV_TYPE }))
+                    .collect::<Vec<_>>(),
+            }))),
+            ("/getState", Model::SpmMain) => {
+                match &data["subDevId"] {
+                    Value::Null => Ok(Some(self.spm_state())),
+                    id if self.is_sub_dev(id) => Ok(Some(self.spm_subdev_state())),
+                    _ => Err(ERR_DEVICE_ID),
+                }
+            },
+            _ => return None,
+        };
+        Some(match res {
+            Ok(data) => {
+                let reads = ["/info", "/getState", "/subDevList"];
+                if !reads.contains(&url_path) {
+                    self.seq += 1;
+                }
+                (0, data)
+            },
+            Err(error) => (error, None),
+        })
+    }
+
+    fn dimmable_d1(&mut self, data: &Value) -> Result<Option<Value>, u32> {
+        let switch = switch_value(&data["switch"])?;
+        let brightness = u8_in(&data["brightness"], 0, 100)?;
+        let mode = optional(&data["mode"], |v| u8_in(v, 0, 2))?;
+        let brightmin = optional(&data["brightmin"], |v| u8_in(v, 0, 100))?;
+        let brightmax = optional(&data["brightmax"], |v| u8_in(v, 0, 100))?;
+        self.outlets[0].switch = switch;
+        self.brightness = brightness;
+        self.mode = mode.unwrap_or(self.mode);
+        self.brightmin = brightmin.unwrap_or(self.brightmin);
+        self.brightmax = brightmax.unwrap_or(self.brightmax);
+        Ok(None)
+    }
+
+    fn is_sub_dev(&self, sub_dev_id: &Value) -> bool {
+        self.sub_dev_id.as_deref().is_some_and(|id| sub_dev_id == id)
+    }
+
+    fn dimmable_bulb(&mut self, data: &Value) -> Result<Option<Value>, u32> {
+        let ltype = data["ltype"].as_str().ok_or(ERR_PARAMS)?;
+        let settings = &data[ltype];
+        u8_in(&settings["br"], 1, 100)?;
+        match ltype {
+            "white" => {
+                u8_in(&settings["ct"], 0, 100)?;
+            },
+            "color" | "bright" | "goodNight" | "read" | "nightLight"
+                | "party" | "leisure" | "soft" | "colorful" => {
+                // Scenes may use 0, plain colors may not
+                let min = if ltype == "color" { 1 } else { 0 };
+                for channel in ["r", "g", "b"] {
+                    u8_in(&settings[channel], min, 255)?;
+                }
+                optional(&settings["tf"], |v| u8_in(v, 0, 1))?;
+                optional(&settings["sp"], |v| u8_in(v, 1, 100))?;
+            },
+            _ => return Err(ERR_PARAMS),
+        }
+        self.light.insert(ltype.to_owned(), settings.clone());
+        self.ltype = ltype.to_owned();
+        Ok(None)
+    }
+}
+
+/// (current, voltage, active, reactive, apparent power) of an SPM outlet:
+/// 230 V, and a 1 A load per outlet number with a power factor of 0.8 when
+/// on. Values are in hundredths of the unit.
+fn spm_load(i: usize, outlet: &Outlet) -> (u32, u32, u32, u32, u32) {
+    if outlet.switch == "on" {
+        let amps = i as u32 + 1;
+        (100 * amps, 23000, 18400 * amps, 13800 * amps, 23000 * amps)
+    } else {
+        (0, 23000, 0, 0, 0)
+    }
+}
+
+fn merge(target: &mut Value, other: Value) {
+    if let (Some(target), Value::Object(other)) = (target.as_object_mut(), other) {
+        target.extend(other);
+    }
+}
+
+fn switch_value(value: &Value) -> Result<String, u32> {
+    match value.as_str() {
+        Some(v @ ("on" | "off")) => Ok(v.to_owned()),
+        _ => Err(ERR_PARAMS),
+    }
+}
+
+fn startup_value(value: &Value) -> Result<String, u32> {
+    match value.as_str() {
+        Some(v @ ("on" | "off" | "stay")) => Ok(v.to_owned()),
+        _ => Err(ERR_PARAMS),
+    }
+}
+
+fn pulse_width(value: &Value) -> Result<u32, u32> {
+    match value.as_u64() {
+        Some(width) if width % 500 == 0 && (500..=3_600_000).contains(&width) => Ok(width as u32),
+        _ => Err(ERR_PARAMS),
+    }
+}
+
+fn u8_in(value: &Value, min: u8, max: u8) -> Result<u8, u32> {
+    match value.as_u64() {
+        Some(v) if (min as u64..=max as u64).contains(&v) => Ok(v as u8),
+        _ => Err(ERR_PARAMS),
+    }
+}
+
+fn optional<T>(value: &Value, parse: impl Fn(&Value) -> Result<T, u32>) -> Result<Option<T>, u32> {
+    if value.is_null() { Ok(None) } else { parse(value).map(Some) }
+}
+
+/// Validates every `{ "outlet": n, ... }` item before applying any of them
+fn for_each_outlet(
+    items: &Value,
+    outlets: usize,
+    mut apply: impl FnMut(usize, &Value) -> Result<(), u32>,
+) -> Result<Option<Value>, u32> {
+    let items = items.as_array().ok_or(ERR_PARAMS)?;
+    let mut indexes = Vec::with_capacity(items.len());
+    for item in items {
+        match item["outlet"].as_u64() {
+            Some(i) if (i as usize) < outlets => indexes.push(i as usize),
+            _ => return Err(ERR_PARAMS),
+        }
+    }
+    for (i, item) in indexes.into_iter().zip(items) {
+        apply(i, item)?;
+    }
+    Ok(None)
+}
+
+// Implementation
+// ===================================================================
+
+#[derive(Clone)]
+pub struct Simulator {
+    state: Arc<Mutex<State>>,
+}
+
+impl Simulator {
+    pub fn new(model: Model) -> Simulator {
+        Simulator { state: Arc::new(Mutex::new(State::new(model))) }
+    }
+
+    pub fn model(&self) -> Model {
+        self.state.lock().unwrap().model
+    }
+
+    /// Turns off an outlet of the SPM sub-device as its protection would,
+    /// reporting the raw `rsn` codes in its fault state. The meaning of
+    /// these codes is not documented, so the simulator does not pick them.
+    pub fn trip(&self, outlet: usize, rsn: Vec<u32>) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(outlet) = state.sub_outlets.get_mut(outlet) {
+            outlet.switch = "off".to_owned();
+            outlet.tripped = rsn;
+        }
+    }
+
+    /// Handles a request body sent to `/zeroconf{url_path}`. Returns `None`
+    /// if the simulated model does not have that endpoint.
+    pub fn handle(&self, url_path: &str, req: &Value) -> Option<Value> {
+        let mut state = self.state.lock().unwrap();
+        let (error, data) = state.handle(url_path, req)?;
+        let mut res = json!({ "seq": state.seq, "error": error });
+        if let Some(data) = data {
+            res["data"] = data;
+        }
+        Some(res)
+    }
+
+    async fn serve_request(self, req: Request<Body>) -> Response<Body> {
+        let Some(url_path) = req.uri().path().strip_prefix("/zeroconf").map(str::to_owned) else {
+            return status(StatusCode::NOT_FOUND);
+        };
+        if req.method() != Method::POST {
+            return status(StatusCode::METHOD_NOT_ALLOWED);
+        }
+        let Ok(body) = hyper::body::to_bytes(req.into_body()).await else {
+            return status(StatusCode::BAD_REQUEST);
+        };
+        let res = match serde_json::from_slice::<Value>(&body) {
+            Ok(body) => self.handle(&url_path, &body),
+            Err(_) => Some(json!({ "seq": self.state.lock().unwrap().seq, "error": 400 })),
+        };
+        match res {
+            Some(res) => Response::builder()
+                .header("Content-Type", "application/json")
+                .body(Body::from(res.to_string()))
+                .unwrap(),
+            None => status(StatusCode::NOT_FOUND),
+        }
+    }
+
+    /// Binds to `addr` and serves requests in a background task. Returns the
+    /// bound address (useful when binding to port 0).
+    pub fn spawn(&self, addr: SocketAddr) -> anyhow::Result<SocketAddr> {
+        let (local_addr, server) = self.bind(addr)?;
+        tokio::spawn(server);
+        Ok(local_addr)
+    }
+
+    /// Binds to `addr` and serves requests until the returned future is
+    /// dropped.
+    pub async fn serve(&self, addr: SocketAddr) -> anyhow::Result<()> {
+        let (_, server) = self.bind(addr)?;
+        Ok(server.await?)
+    }
+
+    fn bind(&self, addr: SocketAddr)
+        -> anyhow::Result<(SocketAddr, impl Future<Output = hyper::Result<()>> + Send)>
+    {
+        let sim = self.clone();
+        let make_service = make_service_fn(move |_| {
+            let sim = sim.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req| {
+                    let sim = sim.clone();
+                    async move { Ok::<_, Infallible>(sim.serve_request(req).await) }
+                }))
+            }
+        });
+        let server = Server::try_bind(&addr)?
+            .http1_title_case_headers(true)
+            .serve(make_service);
+        Ok((server.local_addr(), server))
+    }
+}
+
+fn status(code: StatusCode) -> Response<Body> {
+    Response::builder().status(code).body(Body::empty()).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sonoff_lib::bulb::{SonoffBulb, DevReqBulbColorType, Scene};
+    use sonoff_lib::device::SonoffDevice;
+    use sonoff_lib::dimmable::SonoffDimmable;
+    use sonoff_lib::dimmer::{DimmerMode, SonoffDimmer};
+    use sonoff_lib::error::{DevErrorCode, SonoffError};
+    use sonoff_lib::mini_r3::{SonoffMiniR3, DevDataR3Switch};
+    use sonoff_lib::multi_switchable::SonoffMultiSwitchable;
+    use sonoff_lib::power_meter::{SonoffPowerMeter, DevDataSPMSwitch, FaultReason};
+    use sonoff_lib::switch::SonoffSwitch;
+    use sonoff_lib::values::{Brightness, PulseWidth, Rgb};
+    use sonoff_lib::switchable::{SonoffSwitchable, SwitchState, StartupState};
+
+    fn spawn(model: Model) -> SonoffDevice {
+        let addr = Simulator::new(model).spawn(([127, 0, 0, 1], 0).into()).unwrap();
+        SonoffDevice::new(format!("http://{addr}"))
+    }
+
+    #[tokio::test]
+    async fn test_switch() {
+        let dev = spawn(Model::BasicR3);
+        let switch = SonoffSwitch::from(&dev);
+        switch.on().await.unwrap();
+        assert!(switch.get_switch().await.unwrap());
+        switch.toggle().await.unwrap();
+        assert!(!switch.get_switch().await.unwrap());
+        switch.pulse(PulseWidth::try_from(1500).unwrap()).await.unwrap();
+        switch.set_startup(StartupState::Stay).await.unwrap();
+        let info = dev.get_info().await.unwrap();
+        assert_eq!(info.per_device_info["pulseWidth"], 1500);
+        assert_eq!(info.per_device_info["startup"], "stay");
+    }
+
+    #[tokio::test]
+    async fn test_invalid_params() {
+        let dev = spawn(Model::BasicR3);
+        let err = dev.__request("/startup", serde_json::json!({ "startup": "stya" })).await.unwrap_err();
+        assert!(matches!(err, SonoffError::Device { code: DevErrorCode::InvalidParams, .. }));
+        let err = dev.__request("/pulse", serde_json::json!({ "pulse": "on", "pulseWidth": 700 })).await.unwrap_err();
+        assert!(matches!(err, SonoffError::Device { code: DevErrorCode::InvalidParams, .. }));
+    }
+
+    #[tokio::test]
+    async fn test_dimmer() {
+        let dev = spawn(Model::D1);
+        let dimmer = SonoffDimmer::from(&dev);
+        dimmer.on().await.unwrap();
+        dimmer.dim(Brightness::try_from(30).unwrap()).await.unwrap();
+        let info = dimmer.get_info().await.unwrap();
+        assert_eq!(info.switch, SwitchState::On);
+        assert_eq!(info.brightness, 30);
+        dimmer.set_calibration(10, 90).await.unwrap();
+        dimmer.set_mode(DimmerMode::LeadingEdge).await.unwrap();
+        let info = dimmer.get_info().await.unwrap();
+        assert_eq!((info.mode, info.brightmin, info.brightmax), (1, 10, 90));
+        assert_eq!(info.brightness, 30);
+    }
+
+    #[tokio::test]
+    async fn test_bulb() {
+        let dev = spawn(Model::B05Bl);
+        let bulb = SonoffBulb::from(&dev);
+        bulb.color(Brightness::try_from(40).unwrap(), Rgb::new(255, 1, 1).unwrap()).await.unwrap();
+        let info = bulb.get_info().await.unwrap();
+        let DevReqBulbColorType::Color(rgb) = info.color_type else {
+            panic!("bulb not in color mode");
+        };
+        assert_eq!((rgb.br, rgb.r, rgb.g, rgb.b), (40, 255, 1, 1));
+        bulb.scene(Scene::Colorful).await.unwrap();
+        let info = bulb.get_info().await.unwrap();
+        assert_eq!(info.color_type.scene().unwrap().0, Scene::Colorful);
+    }
+
+    #[tokio::test]
+    async fn test_bulb_dim() {
+        let dev = spawn(Model::B05Bl);
+        let bulb = SonoffBulb::from(&dev);
+        bulb.color(Brightness::try_from(100).unwrap(), Rgb::new(255, 1, 1).unwrap()).await.unwrap();
+        let other = SonoffBulb::from(&dev);
+        let (first, second) = tokio::join!(
+            bulb.dim(Brightness::try_from(10).unwrap()),
+            other.dim(Brightness::try_from(20).unwrap()),
+        );
+        first.unwrap();
+        second.unwrap();
+        let info = bulb.get_info().await.unwrap();
+        let DevReqBulbColorType::Color(rgb) = info.color_type else {
+            panic!("bulb not in color mode");
+        };
+        assert_eq!((rgb.r, rgb.g, rgb.b), (255, 1, 1));
+        assert!(rgb.br == 10 || rgb.br == 20);
+    }
+
+    #[tokio::test]
+    async fn test_bulb_dim_and_color() {
+        let dev = spawn(Model::B05Bl);
+        let bulb = SonoffBulb::from(&dev);
+        bulb.color(Brightness::try_from(100).unwrap(), Rgb::new(255, 1, 1).unwrap()).await.unwrap();
+        let other = SonoffBulb::from(&dev);
+        let (first, second) = tokio::join!(
+            bulb.dim(Brightness::try_from(10).unwrap()),
+            other.color(Brightness::try_from(40).unwrap(), Rgb::new(1, 1, 255).unwrap()),
+        );
+        first.unwrap();
+        second.unwrap();
+        // The dim must not write back the red it read
+        let info = bulb.get_info().await.unwrap();
+        let DevReqBulbColorType::Color(rgb) = info.color_type else {
+            panic!("bulb not in color mode");
+        };
+        assert_eq!((rgb.r, rgb.g, rgb.b), (1, 1, 255));
+    }
+
+    #[tokio::test]
+    async fn test_mini_r3() {
+        let dev = spawn(Model::MiniR3);
+        let mini_r3 = SonoffMiniR3::from(&dev);
+        mini_r3.set_switches(vec![
+            DevDataR3Switch { outlet: 2, switch: SwitchState::On },
+        ]).await.unwrap();
+        let info = dev.get_info().await.unwrap();
+        assert_eq!(info.per_device_info["switches"][2]["switch"], "on");
+        assert_eq!(info.per_device_info["switches"][1]["switch"], "off");
+    }
+
+    #[tokio::test]
+    async fn test_power_meter() {
+        let dev = spawn(Model::SpmMain);
+        let spm = SonoffPowerMeter::from(&dev);
+        let subdevs = spm.get_subdevs().await.unwrap();
+        let sub_dev_id = subdevs.sub_dev_list[0].sub_dev_id.clone();
+        spm.set_switches(sub_dev_id.clone(), vec![
+            DevDataSPMSwitch { outlet: 1, switch: SwitchState::On },
+        ]).await.unwrap();
+        let status = spm.subdev_status(sub_dev_id.clone()).await.unwrap();
+        assert_eq!(status.switches[1].switch, SwitchState::On);
+        let reading = spm.read_power(sub_dev_id.clone(), 1).await.unwrap();
+        assert!((reading.current - 2.0).abs() < 1e-9);
+        assert!((reading.power_factor.unwrap() - 0.8).abs() < 1e-9);
+        let readings = spm.read_power_all(sub_dev_id.clone()).await.unwrap();
+        assert_eq!(readings[0].power_factor, None);
+        assert!((readings[0].voltage - 230.0).abs() < 1e-9);
+        assert_eq!(spm.status().await.unwrap().deviceid, "1000abcdef");
+    }
+
+    #[tokio::test]
+    async fn test_subdevs() {
+        let dev = spawn(Model::SpmMain);
+        let spm = SonoffPowerMeter::from(&dev);
+        let list = spm.get_subdevs().await.unwrap().sub_dev_list;
+        assert_eq!(list[0].sub_dev_id, SPM_SUBDEV_ID);
+        let sub_device = spm.sub_device(SPM_SUBDEV_ID);
+        sub_device.set_outlet_startup(3, StartupState::On).await.unwrap();
+        sub_device.set_outlet_pulse(3, Some(PulseWidth::try_from(2000).unwrap())).await.unwrap();
+        assert_eq!(sub_device.get_outlet_startup(3).await.unwrap(), StartupState::On);
+        assert_eq!(sub_device.get_outlet_pulse(3).await.unwrap(), Some(2000));
+        assert_eq!(sub_device.get_outlet_pulse(2).await.unwrap(), None);
+        assert!(spm.sub_device("01000d3e4f").outlet_on(0).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_faults() {
+        let sim = Simulator::new(Model::SpmMain);
+        let addr = sim.spawn(([127, 0, 0, 1], 0).into()).unwrap();
+        let dev = SonoffDevice::new(format!("http://{addr}"));
+        let sub_device = SonoffPowerMeter::from(&dev).sub_device(SPM_SUBDEV_ID);
+        assert!(sub_device.faults().await.unwrap().is_ok());
+        sub_device.outlet_on(2).await.unwrap();
+        sim.trip(2, vec![7]);
+        assert!(!sub_device.get_outlet(2).await.unwrap());
+        let report = sub_device.faults().await.unwrap();
+        assert_eq!(report.channels.len(), 1);
+        assert_eq!((report.channels[0].outlet, &report.channels[0].reasons[..]), (2, &[FaultReason::Unknown(7)][..]));
+        sub_device.outlet_on(2).await.unwrap();
+        assert!(sub_device.faults().await.unwrap().is_ok());
+    }
+
+    /// Same code for single- and multi-channel relays
+    async fn drive_outlets(relay: &dyn SonoffMultiSwitchable) {
+        let last = relay.outlet_count() - 1;
+        relay.outlet_on(last).await.unwrap();
+        relay.set_outlet_startup(last, StartupState::Stay).await.unwrap();
+        relay.set_outlet_pulse(last, Some(PulseWidth::try_from(1000).unwrap())).await.unwrap();
+        relay.outlet_toggle(last).await.unwrap();
+        assert!(!relay.get_outlet(last).await.unwrap());
+        assert!(relay.outlet_on(last + 1).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_multi_switchable() {
+        let dev = spawn(Model::BasicR3);
+        drive_outlets(&SonoffSwitch::from(&dev)).await;
+        assert_eq!(dev.get_info().await.unwrap().per_device_info["startup"], "stay");
+
+        let dev = spawn(Model::MiniR3);
+        drive_outlets(&SonoffMiniR3::from(&dev)).await;
+        let info = SonoffMiniR3::from(&dev).get_info().await.unwrap();
+        assert_eq!(info.configure[3].startup, StartupState::Stay);
+        assert_eq!(info.pulses[3].width, 1000);
+
+        let dev = spawn(Model::SpmMain);
+        let spm = SonoffPowerMeter::from(&dev);
+        let sub_dev_id = spm.get_subdevs().await.unwrap().sub_dev_list[0].sub_dev_id.clone();
+        drive_outlets(&spm.sub_device(sub_dev_id)).await;
+    }
+
+    #[tokio::test]
+    async fn test_led() {
+        for model in [Model::BasicR3, Model::D1, Model::MiniR3, Model::SpmMain] {
+            let dev = spawn(model);
+            assert!(dev.get_led().await.unwrap());
+            dev.set_led(false).await.unwrap();
+            assert!(!dev.get_led().await.unwrap());
+        }
+        assert!(spawn(Model::B05Bl).set_led(false).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_unsupported_endpoint() {
+        let dev = spawn(Model::BasicR3);
+        let err = SonoffPowerMeter::from(&dev).get_subdevs().await.unwrap_err();
+        assert!(matches!(err, SonoffError::HttpStatus(status) if status == 404));
+    }
+}
@@ -0,0 +1,46 @@
+use std::net::SocketAddr;
+
+use anyhow::Result;
+use clap::{Parser, ValueEnum};
+
+use sonoff_sim::{Model, Simulator};
+
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+struct Cli {
+    /// Simulated device model
+    #[arg(long, value_enum, default_value_t = SimModel::Basicr3)]
+    model: SimModel,
+    /// Address to listen on
+    #[arg(long, default_value = "127.0.0.1:8081")]
+    listen: SocketAddr,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum SimModel {
+    Basicr3,
+    D1,
+    B05bl,
+    Minir3,
+    SpmMain,
+}
+
+impl From<SimModel> for Model {
+    fn from(value: SimModel) -> Self {
+        match value {
+            SimModel::Basicr3 => Model::BasicR3,
+            SimModel::D1 => Model::D1,
+            SimModel::B05bl => Model::B05Bl,
+            SimModel::Minir3 => Model::MiniR3,
+            SimModel::SpmMain => Model::SpmMain,
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Cli::parse();
+    let sim = Simulator::new(args.model.into());
+    println!("Simulating {:?} on http://{}", sim.model(), args.listen);
+    sim.serve(args.listen).await
+}